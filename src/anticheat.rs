@@ -0,0 +1,195 @@
+// src/anticheat.rs
+// Validation of positions reported in ClientData (not used with authoritative movement, see movement.rs).
+// Each update is compared to the last accepted position and the time since it was accepted, capped at
+// MAX_MOVE_WINDOW plus the round trip time so a client can't save up movement by not sending updates.
+// Moving faster than allowed, jumping far beyond that (teleport), standing inside a solid voxel or passing
+// through one on the way from the last accepted position is a violation: the update is rejected and the
+// client is rubber-banded back with a MovementCorrection (input seq 0).
+// Violations add to a per-player score that is kept for the session and exported as a metric.
+
+use crate::chunk::VOXEL_AIR;
+use crate::client::Client;
+use crate::clock::current_tick;
+use crate::config::CONFIG;
+use crate::metrics::*;
+use crate::movement::{PLAYER_HEIGHT, PLAYER_WIDTH, TERMINAL_VELOCITY};
+use crate::outbound::Priority;
+use crate::protocol::Message;
+use crate::world::World;
+use std::time::{Duration, Instant};
+
+const MAX_MOVE_WINDOW: Duration = Duration::from_millis(300); // most time one update may account for, plus rtt
+const BOX_INSET: f32 = 0.1; // the player's box is shrunk by this, touching a floor or wall is not inside it
+const PATH_STEP: f32 = 0.25; // blocks between the points checked on the way to a new position
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MovementViolation {
+    Speed,        // moved faster than CONFIG.max_move_speed (falling up to terminal velocity)
+    Teleport,     // moved more than CONFIG.teleport_distance beyond what the speed allows
+    InsideSolid,  // the player's box overlaps a solid voxel
+    ThroughSolid, // passed through a solid voxel on the way from the last accepted position
+}
+
+impl MovementViolation {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MovementViolation::Speed => "speed",
+            MovementViolation::Teleport => "teleport",
+            MovementViolation::InsideSolid => "inside_solid",
+            MovementViolation::ThroughSolid => "through_solid",
+        }
+    }
+
+    // how much the violation adds to the player's score
+    pub fn weight(&self) -> u32 {
+        match self {
+            MovementViolation::Speed => 1,
+            MovementViolation::Teleport => 5,
+            MovementViolation::InsideSolid => 2,
+            MovementViolation::ThroughSolid => 2,
+        }
+    }
+}
+
+pub struct MovementCheck {
+    pub position: (f32, f32, f32), // last accepted position
+    pub accepted_at: Instant,
+    pub reset_at: Option<Instant>, // when the client was last rubber-banded
+    pub score: u32,
+}
+
+impl MovementCheck {
+    pub fn new(position: (f32, f32, f32)) -> Self {
+        MovementCheck {
+            position,
+            accepted_at: Instant::now(),
+            reset_at: None,
+            score: 0,
+        }
+    }
+}
+
+// true if the player's box at the position, shrunk by BOX_INSET, overlaps a solid voxel.
+// unloaded chunks are not checked
+fn inside_solid(world: &World, position: (f32, f32, f32)) -> bool {
+    let half_width = PLAYER_WIDTH / 2.0 - BOX_INSET;
+    let (min_x, max_x) = (position.0 - half_width, position.0 + half_width);
+    let (min_y, max_y) = (
+        position.1 + BOX_INSET,
+        position.1 + PLAYER_HEIGHT - BOX_INSET,
+    );
+    let (min_z, max_z) = (position.2 - half_width, position.2 + half_width);
+    for x in min_x.floor() as i32..=max_x.floor() as i32 {
+        for y in min_y.floor() as i32..=max_y.floor() as i32 {
+            for z in min_z.floor() as i32..=max_z.floor() as i32 {
+                if world
+                    .get_voxel_at(x, y, z)
+                    .is_some_and(|id| id != VOXEL_AIR)
+                {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// true if the player's box is inside a solid voxel somewhere on the straight path between two positions,
+// checked every PATH_STEP blocks. the end points are not checked
+fn path_through_solid(world: &World, from: (f32, f32, f32), to: (f32, f32, f32)) -> bool {
+    let (dx, dy, dz) = (to.0 - from.0, to.1 - from.1, to.2 - from.2);
+    let distance = (dx * dx + dy * dy + dz * dz).sqrt();
+    let steps = (distance / PATH_STEP).ceil() as u32;
+    (1..steps).any(|step| {
+        let t = step as f32 / steps as f32;
+        inside_solid(world, (from.0 + dx * t, from.1 + dy * t, from.2 + dz * t))
+    })
+}
+
+// checks a reported position against the last accepted one, returns the position the server keeps.
+// on a violation the client is rubber-banded to the last accepted position
+pub fn validate_position(
+    client: &mut Client,
+    world: &World,
+    position: (f32, f32, f32),
+) -> (f32, f32, f32) {
+    if !CONFIG.movement_validation {
+        return position;
+    }
+    let now = Instant::now();
+    let max_elapsed = MAX_MOVE_WINDOW + Duration::from_secs_f64(client.rtt_ms.max(0.0) / 1000.0);
+    let check = &mut client.movement_check;
+    let Some(violation) = find_violation(check, world, position, max_elapsed, now) else {
+        check.position = position;
+        check.accepted_at = now;
+        return position;
+    };
+
+    // updates sent before the client got the correction are dropped without counting them again
+    let grace = Duration::from_millis(client.rtt_ms as u64 * 2 + 250);
+    if check
+        .reset_at
+        .is_some_and(|reset_at| now.duration_since(reset_at) < grace)
+    {
+        return check.position;
+    }
+    check.reset_at = Some(now);
+    check.score += violation.weight();
+    println!(
+        "Movement violation {:?} from client_id:{} at {:?}, score {}",
+        violation, client.id, position, check.score
+    );
+    //metrics
+    MOVEMENT_VIOLATIONS_TOTAL
+        .with_label_values(&[violation.label()])
+        .inc();
+    MOVEMENT_VIOLATION_SCORE
+        .with_label_values(&[&client.id.to_string()])
+        .set(check.score as i64);
+
+    let data = Message::MovementCorrection {
+        input_seq: 0,
+        tick: current_tick(),
+        position: check.position,
+        velocity_y: 0.0,
+        on_ground: false,
+    }
+    .encode();
+    client.outbound.push_data(Priority::Control, data);
+    client.movement_check.position
+}
+
+fn find_violation(
+    check: &MovementCheck,
+    world: &World,
+    position: (f32, f32, f32),
+    max_elapsed: Duration,
+    now: Instant,
+) -> Option<MovementViolation> {
+    if inside_solid(world, position) {
+        return Some(MovementViolation::InsideSolid);
+    }
+    let elapsed = now
+        .duration_since(check.accepted_at)
+        .min(max_elapsed)
+        .as_secs_f32();
+    let dx = position.0 - check.position.0;
+    let dy = position.1 - check.position.1;
+    let dz = position.2 - check.position.2;
+    let horizontal = (dx * dx + dz * dz).sqrt();
+    // distance moved beyond what is allowed, falling may be faster than walking
+    let excess = (horizontal - CONFIG.max_move_speed * elapsed)
+        .max(dy - CONFIG.max_move_speed * elapsed)
+        .max(-dy - TERMINAL_VELOCITY * elapsed)
+        - CONFIG.movement_tolerance;
+    if excess > CONFIG.teleport_distance {
+        Some(MovementViolation::Teleport)
+    } else if excess > 0.0 {
+        Some(MovementViolation::Speed)
+    } else if path_through_solid(world, check.position, position) {
+        // after the speed checks, the path is short
+        Some(MovementViolation::ThroughSolid)
+    } else {
+        None
+    }
+}
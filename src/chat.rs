@@ -0,0 +1,192 @@
+// src/chat.rs
+// Text chat. Player messages and server system messages (join/leave, admin broadcasts) share one pipeline.
+// Clients send Chat with the target id (whisper only, else 0), the server sends it with the sender id (0 for system).
+
+use crate::client::{Client, ClientManager};
+use crate::config::CONFIG;
+use crate::metrics::*;
+use crate::outbound::Priority;
+use crate::protocol::Message;
+use crate::world::World;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+pub const SYSTEM_SENDER_ID: u32 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ChatChannel {
+    Global = 0,
+    Proximity = 1, // players within CONFIG.chat_proximity_radius of the sender
+    Whisper = 2,   // single target player
+    System = 3,    // server only
+}
+
+impl ChatChannel {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ChatChannel::Global),
+            1 => Some(ChatChannel::Proximity),
+            2 => Some(ChatChannel::Whisper),
+            3 => Some(ChatChannel::System),
+            _ => None,
+        }
+    }
+}
+
+// checks a chat message from a client, Err holds the reason told to the sender
+pub fn validate_chat(channel: ChatChannel, text: &str) -> Result<(), String> {
+    if channel == ChatChannel::System {
+        return Err("invalid chat channel".to_string());
+    }
+    if text.len() > CONFIG.chat_max_length {
+        return Err(format!(
+            "chat message too long ({} bytes, max {})",
+            text.len(),
+            CONFIG.chat_max_length
+        ));
+    }
+    if text.trim().is_empty() || text.chars().any(|c| c.is_control()) {
+        return Err("chat message is empty or has control characters".to_string());
+    }
+    Ok(())
+}
+
+// sliding window rate limit, records the message when it is allowed
+fn allow_message(client: &mut Client, now: Instant) -> bool {
+    let window = Duration::from_millis(CONFIG.chat_rate_window_ms);
+    while client
+        .chat_sent
+        .front()
+        .is_some_and(|&sent| now.duration_since(sent) > window)
+    {
+        client.chat_sent.pop_front();
+    }
+    if client.chat_sent.len() >= CONFIG.chat_rate_limit {
+        return false;
+    }
+    client.chat_sent.push_back(now);
+    true
+}
+
+pub async fn process_chat(
+    channel: ChatChannel,
+    target: u32,
+    text: String,
+    client: Arc<RwLock<Client>>,
+    world: Arc<RwLock<World>>,
+    client_manager: Arc<RwLock<ClientManager>>,
+) {
+    let client_id = client.read().await.id;
+    if let Err(reason) = validate_chat(channel, &text) {
+        send_system_to(&client, &reason).await;
+        return;
+    }
+    if let Some(remaining) = client_manager.read().await.mute_remaining(client_id) {
+        send_system_to(
+            &client,
+            &format!("you are muted for {} more seconds", remaining.as_secs() + 1),
+        )
+        .await;
+        return;
+    }
+    if !allow_message(&mut *client.write().await, Instant::now()) {
+        send_system_to(&client, "you are sending messages too fast").await;
+        return;
+    }
+
+    let recipients: Option<HashSet<u32>> = match channel {
+        ChatChannel::Global => None,
+        ChatChannel::Proximity => {
+            let world = world.read().await;
+            let Some(sender) = world.get_player(client_id) else {
+                return;
+            };
+            let radius = CONFIG.chat_proximity_radius;
+            let recipients = world
+                .players
+                .values()
+                .filter(|player| {
+                    let dx = player.position.0 - sender.position.0;
+                    let dy = player.position.1 - sender.position.1;
+                    let dz = player.position.2 - sender.position.2;
+                    dx * dx + dy * dy + dz * dz <= radius * radius
+                })
+                .map(|player| player.id)
+                .collect();
+            Some(recipients)
+        }
+        ChatChannel::Whisper => {
+            if !client_manager.read().await.clients.contains_key(&target) {
+                send_system_to(&client, &format!("player {} is not online", target)).await;
+                return;
+            }
+            // the sender gets its own whisper back as confirmation
+            Some(HashSet::from([target, client_id]))
+        }
+        ChatChannel::System => return,
+    };
+    println!("Chat {:?} from client_id:{}: {}", channel, client_id, text);
+    send_chat(
+        channel,
+        client_id,
+        &text,
+        recipients.as_ref(),
+        &client_manager,
+    )
+    .await;
+}
+
+// delivers a chat message to the recipients, None sends it to everyone
+pub async fn send_chat(
+    channel: ChatChannel,
+    sender_id: u32,
+    text: &str,
+    recipients: Option<&HashSet<u32>>,
+    client_manager: &Arc<RwLock<ClientManager>>,
+) {
+    let data = Message::Chat {
+        channel,
+        peer_id: sender_id,
+        text: text.to_string(),
+    }
+    .encode();
+    let manager = client_manager.read().await;
+    for (client_id, client) in manager.clients.iter() {
+        if recipients.is_some_and(|recipients| !recipients.contains(client_id)) {
+            continue;
+        }
+        client
+            .read()
+            .await
+            .outbound
+            .push_data(Priority::Chat, data.clone());
+    }
+    //metrics
+    CHAT_MESSAGES_TOTAL.inc();
+}
+
+// system message to every client (join/leave notices, admin broadcasts)
+pub async fn broadcast_system(text: &str, client_manager: &Arc<RwLock<ClientManager>>) {
+    send_chat(
+        ChatChannel::System,
+        SYSTEM_SENDER_ID,
+        text,
+        None,
+        client_manager,
+    )
+    .await;
+}
+
+// system message to a single client, used for chat errors
+pub async fn send_system_to(client: &Arc<RwLock<Client>>, text: &str) {
+    let data = Message::Chat {
+        channel: ChatChannel::System,
+        peer_id: SYSTEM_SENDER_ID,
+        text: text.to_string(),
+    }
+    .encode();
+    client.read().await.outbound.push_data(Priority::Chat, data);
+}
@@ -24,22 +24,55 @@ pub struct Chunk {
     pub voxels: Vec<Voxel>, // List of voxels in the chunk
 }
 
+// terrain generator settings, every world has its own
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GeneratorSettings {
+    pub seed: u32,
+    pub frequency: f64, // Lower frequency for smoother transitions
+    pub amplitude: f64, // Controls height variation
+    pub octaves: u32,   // More octaves = smoother terrain
+    pub persistence: f64, // Determines the weight of each successive octave
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        GeneratorSettings {
+            seed: 123456789,
+            frequency: 0.007,
+            amplitude: 0.1,
+            octaves: 2,
+            persistence: 0.5,
+        }
+    }
+}
+
 pub static CHUNK_SIZE: usize = 64;
 pub static CHUNK_HEIGHT: usize = 256;
 
+// voxel ids
+pub const VOXEL_AIR: u8 = 0;
+pub const VOXEL_DIRT: u8 = 1;
+pub const VOXEL_ID_MAX: u8 = VOXEL_DIRT; // highest id the server knows about
+
+// vertical sections used by the section palette encoding
+pub const SECTION_HEIGHT: usize = 16;
+pub const SECTION_EMPTY: u8 = 0; // all air, no further data
+pub const SECTION_SINGLE: u8 = 1; // one voxel id (1 byte) for the whole section
+pub const SECTION_PALETTE: u8 = 2; // palette length (u16), palette ids, bits per index (u8), packed indices
+
 impl Chunk {
     // Generates a new chunk of voxels
-    pub fn new(x: i32, z: i32) -> Self {
+    pub fn new(x: i32, z: i32, generator: &GeneratorSettings) -> Self {
         let mut voxel_index: u32 = 0;
         let mut solid_voxel_count: u32 = 0;
         let mut voxels = Vec::with_capacity(CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE);
-        let simplex = Simplex::new(123456789);
+        let simplex = Simplex::new(generator.seed);
 
         // smoothness factors
-        let frequency = 0.007; // Lower frequency for smoother transitions
-        let amplitude = 0.1; // Controls height variation
-        let octaves = 2; // More octaves = smoother terrain
-        let persistence = 0.5; // Determines the weight of each successive octave
+        let frequency = generator.frequency;
+        let amplitude = generator.amplitude;
+        let octaves = generator.octaves;
+        let persistence = generator.persistence;
 
         // allocate heightmap
         let mut height_map = vec![0u32; CHUNK_SIZE * CHUNK_SIZE];
@@ -99,7 +132,7 @@ impl Chunk {
         );
         Chunk {
             coords: (x, z),
-            voxels: voxels,
+            voxels,
         }
     }
 
@@ -112,9 +145,183 @@ impl Chunk {
         let y = (index / (chunk_size * chunk_size)) % chunk_height;
         let z = (index / chunk_size) % chunk_size;
 
-        return (x, y, z);
+        (x, y, z)
     }
+
+    // return voxel index from chunk local x,y,z coordinates, None if out of bounds
+    pub fn coords_to_index(x: i32, y: i32, z: i32) -> Option<usize> {
+        let chunk_size = CHUNK_SIZE as i32;
+        let chunk_height = CHUNK_HEIGHT as i32;
+        if x < 0 || x >= chunk_size || z < 0 || z >= chunk_size || y < 0 || y >= chunk_height {
+            return None;
+        }
+        Some((y * chunk_size * chunk_size + z * chunk_size + x) as usize)
+    }
+
     pub fn get_voxel(&self, index: usize) -> Option<&Voxel> {
         self.voxels.get(index)
     }
+
+    // section palette encoding: section count (u8) followed by every section bottom to top.
+    // indices are packed least significant bit first in voxel index order
+    pub fn to_section_bytes(&self) -> Vec<u8> {
+        let section_volume = CHUNK_SIZE * CHUNK_SIZE * SECTION_HEIGHT;
+        let mut data = Vec::new();
+        data.push((CHUNK_HEIGHT / SECTION_HEIGHT) as u8);
+
+        for section in self.voxels.chunks(section_volume) {
+            let mut palette: Vec<u8> = Vec::new();
+            let mut palette_index = [usize::MAX; 256];
+            for voxel in section {
+                if palette_index[voxel.id as usize] == usize::MAX {
+                    palette_index[voxel.id as usize] = palette.len();
+                    palette.push(voxel.id);
+                }
+            }
+
+            match palette.len() {
+                1 if palette[0] == VOXEL_AIR => data.push(SECTION_EMPTY),
+                1 => {
+                    data.push(SECTION_SINGLE);
+                    data.push(palette[0]);
+                }
+                palette_length => {
+                    data.push(SECTION_PALETTE);
+                    data.extend((palette_length as u16).to_le_bytes());
+                    data.extend(&palette);
+                    // smallest bit count that fits every palette index
+                    let bits = usize::BITS - (palette_length - 1).leading_zeros();
+                    data.push(bits as u8);
+
+                    let mut packed: u64 = 0;
+                    let mut packed_bits = 0;
+                    for voxel in section {
+                        packed |= (palette_index[voxel.id as usize] as u64) << packed_bits;
+                        packed_bits += bits;
+                        while packed_bits >= 8 {
+                            data.push(packed as u8);
+                            packed >>= 8;
+                            packed_bits -= 8;
+                        }
+                    }
+                    if packed_bits > 0 {
+                        data.push(packed as u8);
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    // decodes to_section_bytes, None if the data is malformed
+    pub fn from_section_bytes(x: i32, z: i32, data: &[u8]) -> Option<Self> {
+        let section_volume = CHUNK_SIZE * CHUNK_SIZE * SECTION_HEIGHT;
+        let mut ids = Vec::with_capacity(CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE);
+        if *data.first()? as usize != CHUNK_HEIGHT / SECTION_HEIGHT {
+            return None;
+        }
+        let mut offset = 1;
+        let mut take = |length: usize| {
+            let bytes = data.get(offset..offset + length)?;
+            offset += length;
+            Some(bytes)
+        };
+
+        for _ in 0..CHUNK_HEIGHT / SECTION_HEIGHT {
+            match take(1)?[0] {
+                SECTION_EMPTY => ids.extend((0..section_volume).map(|_| VOXEL_AIR)),
+                SECTION_SINGLE => {
+                    let id = take(1)?[0];
+                    ids.extend((0..section_volume).map(|_| id));
+                }
+                SECTION_PALETTE => {
+                    let palette_length = u16::from_le_bytes(take(2)?.try_into().ok()?) as usize;
+                    let palette = take(palette_length)?;
+                    let bits = take(1)?[0] as usize;
+                    if bits == 0 || bits > 8 {
+                        return None;
+                    }
+                    let packed = take((section_volume * bits).div_ceil(8))?;
+                    let mask = (1u16 << bits) - 1;
+                    for i in 0..section_volume {
+                        let bit = i * bits;
+                        let low = packed[bit / 8] as u16;
+                        let high = *packed.get(bit / 8 + 1).unwrap_or(&0) as u16;
+                        let palette_index = (((high << 8) | low) >> (bit % 8)) & mask;
+                        ids.push(*palette.get(palette_index as usize)?);
+                    }
+                }
+                _ => return None,
+            }
+        }
+        if offset != data.len() {
+            return None;
+        }
+        Some(Chunk {
+            coords: (x, z),
+            voxels: ids
+                .into_iter()
+                .enumerate()
+                .map(|(index, id)| Voxel::new(index as u32, id))
+                .collect(),
+        })
+    }
+
+    // sets voxel id at index, returns the previous id
+    pub fn set_voxel(&mut self, index: usize, id: u8) -> Option<u8> {
+        let voxel = self.voxels.get_mut(index)?;
+        let previous_id = voxel.id;
+        voxel.id = id;
+        Some(previous_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voxel_ids(chunk: &Chunk) -> Vec<u8> {
+        chunk.voxels.iter().map(|voxel| voxel.id).collect()
+    }
+
+    fn round_trip(chunk: &Chunk) -> Chunk {
+        let (x, z) = chunk.coords;
+        Chunk::from_section_bytes(x, z, &chunk.to_section_bytes()).unwrap()
+    }
+
+    #[test]
+    fn generated_chunk_round_trips() {
+        let chunk = Chunk::new(3, -2, &GeneratorSettings::default());
+        let decoded = round_trip(&chunk);
+        assert_eq!(decoded.coords, (3, -2));
+        assert_eq!(voxel_ids(&decoded), voxel_ids(&chunk));
+    }
+
+    #[test]
+    fn palettes_of_every_size_round_trip() {
+        let section_volume = CHUNK_SIZE * CHUNK_SIZE * SECTION_HEIGHT;
+        let mut chunk = Chunk {
+            coords: (0, 0),
+            voxels: (0..CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE)
+                .map(|index| Voxel::new(index as u32, VOXEL_AIR))
+                .collect(),
+        };
+        // sections with 1, 2, 3 and 256 palette entries, the rest stays empty
+        for (section, palette_length) in [1usize, 2, 3, 256].into_iter().enumerate() {
+            for i in 0..section_volume {
+                let id = ((i + 1) % palette_length) as u8;
+                chunk.set_voxel(section * section_volume + i, id).unwrap();
+            }
+        }
+        let data = chunk.to_section_bytes();
+        assert_eq!(data[1], SECTION_EMPTY); // 1 entry, all air
+        assert_eq!(voxel_ids(&round_trip(&chunk)), voxel_ids(&chunk));
+
+        // a single solid id
+        for i in 0..section_volume {
+            chunk.set_voxel(i, VOXEL_DIRT).unwrap();
+        }
+        assert_eq!(chunk.to_section_bytes()[1..3], [SECTION_SINGLE, VOXEL_DIRT]);
+        assert_eq!(voxel_ids(&round_trip(&chunk)), voxel_ids(&chunk));
+    }
 }
@@ -1,92 +1,301 @@
-use crate::data::DataIdentifier;
-use std::collections::{HashMap, HashSet};
+use crate::anticheat::MovementCheck;
+use crate::handshake::{
+    CAPABILITY_AUTHORITATIVE_MOVEMENT, CAPABILITY_SPECTATOR, CAPABILITY_VOXEL_UPDATES,
+};
+use crate::metrics::MOVEMENT_VIOLATION_SCORE;
+use crate::movement::Movement;
+use crate::outbound::{OutboundMessage, OutboundQueue, Priority};
+use crate::protocol::Message;
+use crate::session::SuspendedSession;
+use crate::udp::UdpChannel;
+use crate::world::World;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::vec;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock};
 
 #[derive()]
 pub struct Client {
     pub id: u32,                   // Unique ID for the client
+    pub session_token: u64,        // opaque token tying connections (TCP, UDP) to this session
     pub position: (f32, f32, f32), // client's position
     pub rotation: (f32, f32, f32), // client's rotation
     pub state: u32,
+    pub capabilities: u32, // capability flags negotiated in the hello handshake
     pub chunk_demand: Vec<(i32, i32, i32)>,
+    pub loaded_chunks: HashSet<(i32, i32)>, // chunks already sent to the client
+    pub queued_chunks: HashSet<(i32, i32)>, // chunks waiting in the outbound queue
+    pub sending_chunks: HashSet<(i32, i32)>, // chunks being written, loaded once the write succeeded
+    pub visible_players: HashSet<u32>, // players the client currently gets updates for
+    pub visible_entities: HashSet<u32>, // entities the client currently gets updates for
+    pub outbound: Arc<OutboundQueue>,
+    pub shutdown: Arc<Notify>, // notified to stop the connection's read task
+    pub last_seen: Instant,    // when the last frame was received
+    pub synced_tick: u64,      // voxel changes before this tick have been written to the socket
+    pub keepalive_seq: u32,
+    pub keepalive_sent: Option<(u32, Instant)>, // last keepalive sequence number and when it was sent
+    pub rtt_ms: f64,                            // smoothed round trip time
+    pub udp: Option<UdpChannel>, // set once the client sent its first datagram
+    pub chat_sent: VecDeque<Instant>, // when recent chat messages were sent, for rate limiting
+    pub violations: u32,              // invalid messages received
+    pub movement: Movement,           // input queue and physics state for authoritative movement
+    pub movement_check: MovementCheck, // last accepted reported position and violation score
+    pub world: Arc<RwLock<World>>,     // world the client is in, see registry.rs
     pub packet_count_rx: u64,
 }
 
 impl Client {
-    pub fn client_to_bytes(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        // pre allocate length header bytes
-        data.resize(4, 1);
-        // push dataIdentifier (byte index 4)
-        let data_identifier = DataIdentifier::InitializeData;
-        data.push(data_identifier as u8);
+    // voxel changes are only sent for chunks the client holds or is being sent and if it understands them
+    pub fn wants_voxel_updates(&self, chunk_x: i32, chunk_z: i32) -> bool {
+        self.capabilities & CAPABILITY_VOXEL_UPDATES != 0
+            && (self.loaded_chunks.contains(&(chunk_x, chunk_z))
+                || self.sending_chunks.contains(&(chunk_x, chunk_z)))
+    }
+
+    // position comes from simulating the client's inputs instead of from ClientData
+    pub fn has_authoritative_movement(&self) -> bool {
+        self.capabilities & CAPABILITY_AUTHORITATIVE_MOVEMENT != 0
+    }
 
-        // Serialize client ID (byte index 5 to 8)
-        data.extend(self.id.to_le_bytes());
+    // observes without a player body, see handshake.rs
+    pub fn is_spectator(&self) -> bool {
+        self.capabilities & CAPABILITY_SPECTATOR != 0
+    }
+
+    // queues a chunk for sending unless the client has it or it is already waiting in the queue
+    pub fn request_chunk(&mut self, chunk_x: i32, chunk_z: i32) {
+        if self.loaded_chunks.contains(&(chunk_x, chunk_z))
+            || self.sending_chunks.contains(&(chunk_x, chunk_z))
+        {
+            return;
+        }
+        if self.queued_chunks.insert((chunk_x, chunk_z)) {
+            self.outbound
+                .push(Priority::Bulk, OutboundMessage::Chunk(chunk_x, chunk_z));
+        }
+    }
+
+    // called with the world locked when the writer serialized a chunk
+    pub fn start_chunk_send(&mut self, chunk_x: i32, chunk_z: i32) {
+        self.sending_chunks.insert((chunk_x, chunk_z));
+    }
+
+    // the chunk was written, unless it was unloaded while being sent
+    pub fn chunk_sent(&mut self, chunk_x: i32, chunk_z: i32) {
+        if self.sending_chunks.remove(&(chunk_x, chunk_z)) {
+            self.loaded_chunks.insert((chunk_x, chunk_z));
+        }
+    }
 
-        // Serialize position coordinates (byte index 9 to 20)
-        data.extend(self.position.0.to_le_bytes());
-        data.extend(self.position.1.to_le_bytes());
-        data.extend(self.position.2.to_le_bytes());
+    // forgets loaded chunks outside view distance of the client's position and tells the client to drop them.
+    // unloads go through the bulk queue so they stay ordered with chunk sends
+    pub fn unload_distant_chunks(&mut self, view_distance: i32) {
+        let (chunk_x, chunk_z) = World::chunk_coords_of(self.position);
+        let distant_chunks: Vec<(i32, i32)> = self
+            .loaded_chunks
+            .iter()
+            .chain(self.sending_chunks.iter())
+            .filter(|&&(x, z)| {
+                i64::from(x.abs_diff(chunk_x)) > i64::from(view_distance)
+                    || i64::from(z.abs_diff(chunk_z)) > i64::from(view_distance)
+            })
+            .cloned()
+            .collect();
+        for (x, z) in distant_chunks {
+            self.loaded_chunks.remove(&(x, z));
+            self.sending_chunks.remove(&(x, z));
+            self.outbound
+                .push_data(Priority::Bulk, Message::ChunkUnload { x, z }.encode());
+        }
+    }
 
-        // serialize client state (byte index 21)
-        data.extend(self.state.to_le_bytes());
+    // sends data over UDP if the client has bound it, returns false when the caller should use TCP instead
+    pub fn send_unreliable(&mut self, data: &[u8]) -> bool {
+        match &mut self.udp {
+            Some(udp) => {
+                udp.send(data);
+                true
+            }
+            None => false,
+        }
+    }
 
-        // serialize length of the data & insert to bytes 0 to 3
-        let length = data.len() as u32;
-        let length_bytes = length.to_le_bytes();
-        // add length to first 4 bytes of data
-        data[..4].copy_from_slice(&length_bytes);
+    pub fn demands_chunk(&self, chunk_x: i32, chunk_z: i32) -> bool {
+        self.chunk_demand
+            .iter()
+            .any(|&(x, z, _)| x == chunk_x && z == chunk_z)
+    }
 
-        data
+    pub fn client_to_bytes(&self) -> Vec<u8> {
+        Message::InitializeData {
+            client_id: self.id,
+            position: self.position,
+            state: self.state,
+            session_token: self.session_token,
+        }
+        .encode()
     }
 }
 
 pub struct ClientManager {
     pub clients: HashMap<u32, Arc<RwLock<Client>>>,
-    pub demanded_chunks: Vec<(i32, i32, i32)>
+    pub sessions: HashMap<u64, u32>, // session token -> client id
+    pub udp_tokens: HashMap<u64, u32>, // udp token -> client id, binds UDP but can't resume a session
+    pub suspended: HashMap<u64, SuspendedSession>, // lost sessions that can still be resumed
+    pub mutes: HashMap<u32, Instant>, // client id -> muted until, kept across session resume
+    pub spectators: HashSet<u32>,     // connected clients without a player body
+    next_client_id: u32,
+}
+
+impl Default for ClientManager {
+    fn default() -> Self {
+        ClientManager::new()
+    }
 }
 
 impl ClientManager {
     pub fn new() -> Self {
         ClientManager {
             clients: HashMap::new(),
-            demanded_chunks: Vec::new(),
+            sessions: HashMap::new(),
+            udp_tokens: HashMap::new(),
+            suspended: HashMap::new(),
+            mutes: HashMap::new(),
+            spectators: HashSet::new(),
+            next_client_id: 1,
         }
     }
     pub async fn add_client(&mut self, client: Arc<RwLock<Client>>) {
-        let client_id = client.read().await.id;
+        let (client_id, is_spectator) = {
+            let client = client.read().await;
+            (client.id, client.is_spectator())
+        };
+        if is_spectator {
+            self.spectators.insert(client_id);
+        }
         self.clients.insert(client_id, client);
     }
 
     pub fn remove_client(&mut self, client_id: u32) {
         self.clients.remove(&client_id);
+        self.spectators.remove(&client_id);
+        let _ = MOVEMENT_VIOLATION_SCORE.remove_label_values(&[&client_id.to_string()]);
+        self.sessions.retain(|_, &mut id| id != client_id);
+        self.udp_tokens.retain(|_, &mut id| id != client_id);
+        self.mutes.remove(&client_id);
     }
 
-    pub fn get_client(&self, client_id: u32) -> Option<Arc<RwLock<Client>>> {
-        self.clients.get(&client_id).cloned()
+    // players holding a slot, suspended sessions included since they may come back
+    pub fn player_count(&self) -> usize {
+        self.clients.len() - self.spectators.len() + self.suspended.len()
     }
-    // returns all clients id, position, rotation, state
-    pub async fn get_all_client_data(&self) -> Vec<(u32, (f32, f32, f32), (f32, f32, f32), u32)> {
+
+    // removes the client but keeps its session token so the session can be resumed
+    pub fn suspend_client(&mut self, session: SuspendedSession) {
+        self.clients.remove(&session.client_id);
+        // a resumed connection gets a new udp token
+        self.udp_tokens.retain(|_, &mut id| id != session.client_id);
+        self.suspended.insert(session.session_token, session);
+    }
+
+    pub fn resume_session(&mut self, session_token: u64) -> Option<SuspendedSession> {
+        self.suspended.remove(&session_token)
+    }
+
+    // forgets a session that has been suspended for at least the grace period, returns its client id
+    pub fn expire_session(&mut self, session_token: u64, grace: Duration) -> Option<u32> {
+        let expired = self
+            .suspended
+            .get(&session_token)
+            .is_some_and(|session| session.suspended_at.elapsed() >= grace);
+        if !expired {
+            return None;
+        }
+        let session = self.suspended.remove(&session_token).unwrap();
+        self.sessions.remove(&session_token);
+        self.mutes.remove(&session.client_id);
+        let _ = MOVEMENT_VIOLATION_SCORE.remove_label_values(&[&session.client_id.to_string()]);
+        println!("Session of client_id:{} expired", session.client_id);
+        Some(session.client_id)
+    }
+
+    pub fn mute(&mut self, client_id: u32, duration: Duration) {
+        self.mutes.insert(client_id, Instant::now() + duration);
+    }
+
+    pub fn unmute(&mut self, client_id: u32) {
+        self.mutes.remove(&client_id);
+    }
+
+    // time left on the client's mute, None if it is not muted
+    pub fn mute_remaining(&self, client_id: u32) -> Option<Duration> {
+        self.mutes
+            .get(&client_id)
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+    }
+
+    // ids are never reused while the server runs
+    pub fn allocate_client_id(&mut self) -> u32 {
+        let client_id = self.next_client_id;
+        self.next_client_id = self
+            .next_client_id
+            .checked_add(1)
+            .expect("client ids exhausted");
+        client_id
+    }
+
+    // issues a new random session token for the client
+    pub fn issue_session_token(&mut self, client_id: u32) -> u64 {
+        loop {
+            let token: u64 = rand::random();
+            if let std::collections::hash_map::Entry::Vacant(entry) = self.sessions.entry(token) {
+                entry.insert(client_id);
+                return token;
+            }
+        }
+    }
+
+    // issues a new random udp token for the client's connection. it travels in cleartext in every
+    // datagram, so it is separate from the session token, which would allow taking over the session
+    pub fn issue_udp_token(&mut self, client_id: u32) -> u64 {
+        loop {
+            let token: u64 = rand::random();
+            if let std::collections::hash_map::Entry::Vacant(entry) = self.udp_tokens.entry(token) {
+                entry.insert(client_id);
+                return token;
+            }
+        }
+    }
+
+    // returns id, position, rotation, state of all clients in the world
+    pub async fn get_all_client_data(
+        &self,
+        world: &Arc<RwLock<World>>,
+    ) -> Vec<(u32, (f32, f32, f32), (f32, f32, f32), u32)> {
         let mut client_data = Vec::new();
 
         // Iterate through all clients in the HashMap
         for client_arc in self.clients.values() {
             let client = client_arc.read().await; // Acquire read lock on the client
+            // spectators have no player
+            if !Arc::ptr_eq(&client.world, world) || client.is_spectator() {
+                continue;
+            }
             client_data.push((client.id,client.position, client.rotation, client.state)); // Collect client position
         }
 
         client_data
     }
-    //returns a vec of chunk x,z,distance values based on clients demands & sorted by acending distance
-    pub async fn calculate_demanded_chunks(&mut self) -> Vec<(i32, i32, i32)> {
+    //returns a vec of chunk x,z,distance values based on demands of the world's clients & sorted by acending distance
+    pub async fn calculate_demanded_chunks(&self, world: &Arc<RwLock<World>>) -> Vec<(i32, i32, i32)> {
         let mut filtered_demanded_chunks: HashMap<(i32, i32), i32> = HashMap::new();
         let mut demanded_chunks = Vec::new();
         // Iterate through all clients in the HashMap
         for client_arc in self.clients.values() {
             let client = client_arc.read().await;
+            if !Arc::ptr_eq(&client.world, world) {
+                continue;
+            }
             demanded_chunks.extend(client.chunk_demand.clone());
         }
         // remove duplicates and keep smallest distance
@@ -109,7 +318,6 @@ impl ClientManager {
         // sort entries
         sorted_demanded_chunks.sort_by_key(|&(_, _, distance)| distance);
         
-        self.demanded_chunks = sorted_demanded_chunks;
-        self.demanded_chunks.clone()
+        sorted_demanded_chunks
     }
 }
@@ -0,0 +1,33 @@
+// src/clock.rs
+// Server tick and clock. The tick counts world updates since start, the clock is milliseconds since start.
+// Both are stamped on player snapshots, clients estimate their offset to the clock with TimeSync
+// and render remote players at a fixed delay behind it.
+
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+lazy_static! {
+    static ref SERVER_START: Instant = Instant::now();
+}
+
+static SERVER_TICK: AtomicU64 = AtomicU64::new(0);
+
+// called once at startup so the clock starts with the server, not with its first reader
+pub fn start() {
+    lazy_static::initialize(&SERVER_START);
+}
+
+// milliseconds since the server started
+pub fn server_time_ms() -> u64 {
+    SERVER_START.elapsed().as_millis() as u64
+}
+
+pub fn current_tick() -> u64 {
+    SERVER_TICK.load(Ordering::Relaxed)
+}
+
+// moves to the next tick and returns it, only the world update task calls this
+pub fn advance_tick() -> u64 {
+    SERVER_TICK.fetch_add(1, Ordering::Relaxed) + 1
+}
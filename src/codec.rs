@@ -0,0 +1,280 @@
+// src/codec.rs
+// Length-prefixed framing shared by the TCP and WebSocket paths.
+// Wire format: [total length incl. header (u32 LE)][identifier (u8)][payload]
+
+use std::fmt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const LENGTH_HEADER_SIZE: usize = 4;
+// smallest valid frame is the length header plus the identifier byte
+pub const MIN_FRAME_SIZE: usize = LENGTH_HEADER_SIZE + 1;
+const READ_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(std::io::Error),
+    TooShort(usize),                 // declared length smaller than MIN_FRAME_SIZE
+    TooLarge(usize, usize),          // declared length, max frame size
+    Truncated(usize),                // connection closed with this many bytes of a frame buffered
+    LengthMismatch(usize, usize),    // declared length, received length (single frame messages)
+    UnexpectedMessage(&'static str), // transport message that can't carry a frame, e.g. WebSocket text
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "io error: {}", e),
+            FrameError::TooShort(length) => write!(f, "frame length {} is too short", length),
+            FrameError::TooLarge(length, max) => {
+                write!(f, "frame length {} exceeds maximum {}", length, max)
+            }
+            FrameError::Truncated(buffered) => {
+                write!(
+                    f,
+                    "connection closed mid-frame ({} bytes buffered)",
+                    buffered
+                )
+            }
+            FrameError::LengthMismatch(declared, received) => write!(
+                f,
+                "frame declares {} bytes but {} were received",
+                declared, received
+            ),
+            FrameError::UnexpectedMessage(kind) => write!(f, "unexpected {} message", kind),
+        }
+    }
+}
+
+impl From<std::io::Error> for FrameError {
+    fn from(e: std::io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+// reads the length header and checks it against the limits
+fn check_length(header: &[u8], max_frame_size: usize) -> Result<usize, FrameError> {
+    let length = u32::from_le_bytes(header[..LENGTH_HEADER_SIZE].try_into().unwrap()) as usize;
+    if length < MIN_FRAME_SIZE {
+        return Err(FrameError::TooShort(length));
+    }
+    if length > max_frame_size {
+        return Err(FrameError::TooLarge(length, max_frame_size));
+    }
+    Ok(length)
+}
+
+// Splits a byte stream into frames, returned without the length header
+pub struct FrameDecoder {
+    max_frame_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameDecoder {
+            max_frame_size,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // returns the next complete frame if one is buffered
+    pub fn decode(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        if self.buffer.len() < LENGTH_HEADER_SIZE {
+            return Ok(None);
+        }
+        // length is checked before waiting for the body so nothing is buffered past the limit
+        let length = check_length(&self.buffer, self.max_frame_size)?;
+        if self.buffer.len() < length {
+            return Ok(None);
+        }
+        let frame = self.buffer[LENGTH_HEADER_SIZE..length].to_vec();
+        self.buffer.drain(..length);
+        Ok(Some(frame))
+    }
+
+    // reads until a full frame is available, Ok(None) when the stream closes between frames
+    pub async fn read_frame<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Option<Vec<u8>>, FrameError> {
+        let mut read_buffer = [0u8; READ_BUFFER_SIZE];
+        loop {
+            if let Some(frame) = self.decode()? {
+                return Ok(Some(frame));
+            }
+            let bytes_read = reader.read(&mut read_buffer).await?;
+            if bytes_read == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(FrameError::Truncated(self.buffer.len()));
+            }
+            self.extend(&read_buffer[..bytes_read]);
+        }
+    }
+
+    // decodes a message that carries exactly one frame (e.g. a WebSocket binary message)
+    pub fn decode_message(&self, bytes: &[u8]) -> Result<Vec<u8>, FrameError> {
+        if bytes.len() < LENGTH_HEADER_SIZE {
+            return Err(FrameError::Truncated(bytes.len()));
+        }
+        let length = check_length(bytes, self.max_frame_size)?;
+        if length != bytes.len() {
+            return Err(FrameError::LengthMismatch(length, bytes.len()));
+        }
+        Ok(bytes[LENGTH_HEADER_SIZE..].to_vec())
+    }
+}
+
+// Prefixes identifier + payload with the length header
+pub struct FrameEncoder {
+    max_frame_size: usize,
+}
+
+impl FrameEncoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameEncoder { max_frame_size }
+    }
+
+    pub fn encode(&self, frame: &[u8]) -> Result<Vec<u8>, FrameError> {
+        let length = frame.len() + LENGTH_HEADER_SIZE;
+        if length < MIN_FRAME_SIZE {
+            return Err(FrameError::TooShort(length));
+        }
+        if length > self.max_frame_size || length > u32::MAX as usize {
+            return Err(FrameError::TooLarge(length, self.max_frame_size));
+        }
+        let mut data = Vec::with_capacity(length);
+        data.extend((length as u32).to_le_bytes());
+        data.extend_from_slice(frame);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        FrameEncoder::new(1024).encode(body).unwrap()
+    }
+
+    // a reader that returns at most one byte per read
+    fn one_byte_reader(data: Vec<u8>) -> tokio::io::DuplexStream {
+        let (mut writer, reader) = tokio::io::duplex(1);
+        tokio::spawn(async move {
+            writer.write_all(&data).await.unwrap();
+        });
+        reader
+    }
+
+    #[test]
+    fn decode_waits_for_partial_header_and_split_body() {
+        let mut decoder = FrameDecoder::new(1024);
+        let mut stream = frame(&[1, 2, 3, 4, 5]);
+        stream.extend(frame(&[6]));
+
+        let mut frames = Vec::new();
+        for piece in stream.chunks(3) {
+            decoder.extend(piece);
+            while let Some(frame) = decoder.decode().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, vec![vec![1, 2, 3, 4, 5], vec![6]]);
+        assert!(decoder.decode().unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_rejects_length_below_minimum() {
+        for length in [0u32, 4] {
+            let mut decoder = FrameDecoder::new(1024);
+            decoder.extend(&length.to_le_bytes());
+            assert!(
+                matches!(decoder.decode(), Err(FrameError::TooShort(l)) if l == length as usize)
+            );
+        }
+    }
+
+    #[test]
+    fn decode_rejects_oversized_length_before_the_body_arrives() {
+        let mut decoder = FrameDecoder::new(100);
+        decoder.extend(&1000u32.to_le_bytes());
+        assert!(matches!(
+            decoder.decode(),
+            Err(FrameError::TooLarge(1000, 100))
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_frame_reads_byte_by_byte_until_eof() {
+        let mut stream = frame(&[1, 2, 3]);
+        stream.extend(frame(&[4]));
+        let mut reader = one_byte_reader(stream);
+        let mut decoder = FrameDecoder::new(1024);
+
+        assert_eq!(
+            decoder.read_frame(&mut reader).await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            decoder.read_frame(&mut reader).await.unwrap(),
+            Some(vec![4])
+        );
+        assert_eq!(decoder.read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn read_frame_reports_eof_mid_frame() {
+        // header and part of the body
+        let mut reader = one_byte_reader(frame(&[1, 2, 3, 4])[..6].to_vec());
+        let mut decoder = FrameDecoder::new(1024);
+        assert!(matches!(
+            decoder.read_frame(&mut reader).await,
+            Err(FrameError::Truncated(6))
+        ));
+
+        // partial header
+        let mut reader = one_byte_reader(vec![9, 0]);
+        let mut decoder = FrameDecoder::new(1024);
+        assert!(matches!(
+            decoder.read_frame(&mut reader).await,
+            Err(FrameError::Truncated(2))
+        ));
+    }
+
+    #[test]
+    fn decode_message_takes_exactly_one_frame() {
+        let decoder = FrameDecoder::new(100);
+        assert_eq!(decoder.decode_message(&frame(&[1, 2])).unwrap(), vec![1, 2]);
+
+        assert!(matches!(
+            decoder.decode_message(&[5, 0]),
+            Err(FrameError::Truncated(2))
+        ));
+        assert!(matches!(
+            decoder.decode_message(&4u32.to_le_bytes()),
+            Err(FrameError::TooShort(4))
+        ));
+        assert!(matches!(
+            decoder.decode_message(&1000u32.to_le_bytes()),
+            Err(FrameError::TooLarge(1000, 100))
+        ));
+        // split body and a second frame in the same message
+        let mut data = frame(&[1, 2]);
+        assert!(matches!(
+            decoder.decode_message(&data[..5]),
+            Err(FrameError::LengthMismatch(6, 5))
+        ));
+        data.extend(frame(&[3]));
+        assert!(matches!(
+            decoder.decode_message(&data),
+            Err(FrameError::LengthMismatch(6, 11))
+        ));
+    }
+}
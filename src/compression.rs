@@ -0,0 +1,89 @@
+// src/compression.rs
+// Optional compression of chunk payloads, the algorithm is picked from the negotiated capabilities.
+// Region files (region.rs) use the same algorithms for stored chunks.
+
+use crate::handshake::{CAPABILITY_COMPRESSION_DEFLATE, CAPABILITY_COMPRESSION_ZSTD};
+use crate::metrics::*;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Compression {
+    None = 0,
+    Deflate = 1,
+    Zstd = 2,
+}
+
+impl Compression {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Deflate),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // best algorithm the client supports, zstd is preferred
+    pub fn from_capabilities(capabilities: u32) -> Self {
+        if capabilities & CAPABILITY_COMPRESSION_ZSTD != 0 {
+            Compression::Zstd
+        } else if capabilities & CAPABILITY_COMPRESSION_DEFLATE != 0 {
+            Compression::Deflate
+        } else {
+            Compression::None
+        }
+    }
+
+    // returns the compression actually used and the payload,
+    // data is sent raw if compressing fails or does not make it smaller
+    pub fn compress(self, data: &[u8]) -> (Compression, Vec<u8>) {
+        let timer = Instant::now();
+        let compressed = self.encode(data);
+        if self != Compression::None {
+            CHUNK_COMPRESSION_TIME.observe(timer.elapsed().as_secs_f64() * 1000.0);
+        }
+
+        //metrics
+        CHUNK_BYTES_RAW_TOTAL.inc_by(data.len() as u64);
+        match compressed {
+            Some(compressed) if compressed.len() < data.len() => {
+                CHUNK_BYTES_COMPRESSED_TOTAL.inc_by(compressed.len() as u64);
+                (self, compressed)
+            }
+            _ => {
+                CHUNK_BYTES_COMPRESSED_TOTAL.inc_by(data.len() as u64);
+                (Compression::None, data.to_vec())
+            }
+        }
+    }
+
+    // compresses without metrics, None when compressing fails or for Compression::None
+    pub fn encode(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Compression::None => None,
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).and_then(|_| encoder.finish()).ok()
+            }
+            Compression::Zstd => zstd::encode_all(data, ZSTD_LEVEL).ok(),
+        }
+    }
+
+    pub fn decode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Deflate => {
+                let mut decoded = Vec::new();
+                DeflateDecoder::new(data).read_to_end(&mut decoded)?;
+                Ok(decoded)
+            }
+            Compression::Zstd => zstd::decode_all(data),
+        }
+    }
+}
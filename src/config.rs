@@ -0,0 +1,115 @@
+// src/config.rs
+// Server settings, read once from environment variables with defaults
+
+use std::str::FromStr;
+
+pub struct ServerConfig {
+    pub max_frame_size: usize, // largest frame accepted from a client (bytes, incl. header)
+    pub max_outbound_frame_size: usize, // largest frame the server will send (bytes, incl. header)
+    pub handshake_timeout_ms: u64, // how long a new connection has to send its hello
+    pub player_view_radius: i32, // clients get updates for players within this many chunks
+    pub entity_view_radius: i32, // clients get updates for entities within this many chunks
+    pub chunk_view_distance: i32, // chunks further than this from a client are not sent / get unloaded
+    pub keepalive_interval_ms: u64, // how often the server pings each client
+    pub keepalive_timeout_ms: u64, // silent clients are disconnected after this long
+    pub udp_addr: String,         // address of the optional UDP movement channel, empty disables it
+    pub session_grace_ms: u64,    // how long a lost session can be resumed, 0 disables resuming
+    pub chat_max_length: usize,   // longest chat message accepted (bytes of utf8)
+    pub chat_proximity_radius: f32, // proximity chat reaches players within this many blocks
+    pub chat_rate_limit: usize,   // chat messages a player may send per rate window
+    pub chat_rate_window_ms: u64,
+    pub tls_cert_path: String, // PEM certificate chain, empty disables TLS
+    pub tls_key_path: String,  // PEM private key
+    pub tls_tcp: bool,         // use TLS on the TCP listener when a certificate is configured
+    pub tls_websocket: bool, // use TLS (wss) on the WebSocket listener when a certificate is configured
+    pub tls_reload_interval_ms: u64, // how often the certificate files are checked for changes
+    pub violation_policy: ViolationPolicy, // what happens when a client sends invalid messages
+    pub violation_kick_threshold: u32, // invalid messages before a client is kicked (kick policy)
+    pub authoritative_movement: bool, // simulate movement from client inputs, clients must support it
+    pub movement_validation: bool,    // check positions reported by clients, see anticheat.rs
+    pub max_move_speed: f32,          // blocks per second a player may move, except falling
+    pub movement_tolerance: f32,      // blocks a move may exceed the speed limit by, for jitter
+    pub teleport_distance: f32,       // excess distance counted as a teleport instead of speeding
+    pub worlds: String, // name:seed[:amplitude[:frequency]],... the first world is the default, see registry.rs
+    pub client_world_switch: bool, // clients may move themselves with ChangeWorld, the console always can
+    pub max_players: usize, // players connected or in a suspended session, 0 is unlimited. spectators don't count
+    pub world_dir: String, // region files are stored in a directory per world below this, empty disables saving
+    pub save_interval_ms: u64, // how often modified chunks are written to disk
+}
+
+// handling of invalid client messages, they are always dropped and counted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViolationPolicy {
+    Ignore, // drop silently
+    Warn,   // drop and log
+    Kick,   // drop, log and kick after violation_kick_threshold violations
+}
+
+impl FromStr for ViolationPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(ViolationPolicy::Ignore),
+            "warn" => Ok(ViolationPolicy::Warn),
+            "kick" => Ok(ViolationPolicy::Kick),
+            _ => Err(format!("unknown violation policy {}", value)),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        ServerConfig {
+            max_frame_size: env_or("VOXEL_MAX_FRAME_SIZE", 1024 * 1024),
+            max_outbound_frame_size: env_or("VOXEL_MAX_OUTBOUND_FRAME_SIZE", 16 * 1024 * 1024),
+            handshake_timeout_ms: env_or("VOXEL_HANDSHAKE_TIMEOUT_MS", 5000),
+            player_view_radius: env_or("VOXEL_PLAYER_VIEW_RADIUS", 4),
+            entity_view_radius: env_or("VOXEL_ENTITY_VIEW_RADIUS", 4),
+            chunk_view_distance: env_or("VOXEL_CHUNK_VIEW_DISTANCE", 8),
+            keepalive_interval_ms: env_or("VOXEL_KEEPALIVE_INTERVAL_MS", 2000),
+            keepalive_timeout_ms: env_or("VOXEL_KEEPALIVE_TIMEOUT_MS", 10000),
+            udp_addr: env_or("VOXEL_UDP_ADDR", "127.0.0.1:6971".to_string()),
+            session_grace_ms: env_or("VOXEL_SESSION_GRACE_MS", 30000),
+            chat_max_length: env_or("VOXEL_CHAT_MAX_LENGTH", 256),
+            chat_proximity_radius: env_or("VOXEL_CHAT_PROXIMITY_RADIUS", 32.0),
+            chat_rate_limit: env_or("VOXEL_CHAT_RATE_LIMIT", 5),
+            chat_rate_window_ms: env_or("VOXEL_CHAT_RATE_WINDOW_MS", 5000),
+            tls_cert_path: env_or("VOXEL_TLS_CERT", String::new()),
+            tls_key_path: env_or("VOXEL_TLS_KEY", String::new()),
+            tls_tcp: env_or("VOXEL_TLS_TCP", true),
+            tls_websocket: env_or("VOXEL_TLS_WEBSOCKET", true),
+            tls_reload_interval_ms: env_or("VOXEL_TLS_RELOAD_INTERVAL_MS", 10000),
+            violation_policy: env_or("VOXEL_VIOLATION_POLICY", ViolationPolicy::Kick),
+            violation_kick_threshold: env_or("VOXEL_VIOLATION_KICK_THRESHOLD", 10),
+            authoritative_movement: env_or("VOXEL_AUTHORITATIVE_MOVEMENT", false),
+            movement_validation: env_or("VOXEL_MOVEMENT_VALIDATION", true),
+            max_move_speed: env_or("VOXEL_MAX_MOVE_SPEED", 10.0),
+            movement_tolerance: env_or("VOXEL_MOVEMENT_TOLERANCE", 1.0),
+            teleport_distance: env_or("VOXEL_TELEPORT_DISTANCE", 8.0),
+            worlds: env_or("VOXEL_WORLDS", "world:123456789".to_string()),
+            client_world_switch: env_or("VOXEL_CLIENT_WORLD_SWITCH", true),
+            max_players: env_or("VOXEL_MAX_PLAYERS", 0),
+            world_dir: env_or("VOXEL_WORLD_DIR", "worlds".to_string()),
+            save_interval_ms: env_or("VOXEL_SAVE_INTERVAL_MS", 30000),
+        }
+    }
+}
+
+// reads and parses an environment variable, falling back to default when missing or invalid
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => match value.parse() {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                eprintln!("Invalid value for {} ({}), using default", key, value);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref CONFIG: ServerConfig = ServerConfig::from_env();
+}
@@ -0,0 +1,125 @@
+// src/console.rs
+// Admin commands read line by line from the server's stdin. Output goes to the server log.
+
+use crate::chat::broadcast_system;
+use crate::client::ClientManager;
+use crate::entity::EntityKind;
+use crate::registry::{switch_world, WorldRegistry};
+use crate::world::World;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::RwLock;
+
+// commands:
+//   say <text>            broadcast a system message
+//   mute <id> <seconds>   mute a player
+//   unmute <id>
+//   spawn <item|projectile|npc|vehicle> <x> <y> <z> [world]   spawn an entity at rest
+//   despawn <entity id> [world]
+//   worlds                list the worlds and their player counts
+//   move <id> <world>     move a player to another world
+// commands taking a world use the default world when it is left out
+pub async fn console_task(
+    registry: Arc<WorldRegistry>,
+    client_manager: Arc<RwLock<ClientManager>>,
+) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+        let arguments: Vec<&str> = arguments.split_whitespace().collect();
+        match (command, arguments.as_slice()) {
+            ("", _) => {}
+            ("say", _) if !arguments.is_empty() => {
+                broadcast_system(&arguments.join(" "), &client_manager).await;
+            }
+            ("mute", [client_id, seconds]) => {
+                match (client_id.parse::<u32>(), seconds.parse::<u64>()) {
+                    (Ok(client_id), Ok(seconds)) => {
+                        client_manager
+                            .write()
+                            .await
+                            .mute(client_id, Duration::from_secs(seconds));
+                        println!("Muted client_id:{} for {}s", client_id, seconds);
+                    }
+                    _ => println!("usage: mute <id> <seconds>"),
+                }
+            }
+            ("unmute", [client_id]) => match client_id.parse::<u32>() {
+                Ok(client_id) => {
+                    client_manager.write().await.unmute(client_id);
+                    println!("Unmuted client_id:{}", client_id);
+                }
+                Err(_) => println!("usage: unmute <id>"),
+            },
+            ("spawn", [kind, x, y, z, world @ ..]) if world.len() <= 1 => {
+                match (
+                    EntityKind::from_name(kind),
+                    x.parse::<f32>(),
+                    y.parse::<f32>(),
+                    z.parse::<f32>(),
+                    console_world(&registry, world.first()),
+                ) {
+                    (Some(kind), Ok(x), Ok(y), Ok(z), Some(world)) => {
+                        let entity_id = world.write().await.spawn_entity(
+                            kind,
+                            (x, y, z),
+                            (0.0, 0.0, 0.0),
+                            vec![],
+                            None,
+                        );
+                        println!("Spawned {:?} entity_id:{}", kind, entity_id);
+                    }
+                    _ => println!("usage: spawn <item|projectile|npc|vehicle> <x> <y> <z> [world]"),
+                }
+            }
+            ("despawn", [entity_id, world @ ..]) if world.len() <= 1 => {
+                match (
+                    entity_id.parse::<u32>(),
+                    console_world(&registry, world.first()),
+                ) {
+                    (Ok(entity_id), Some(world)) => {
+                        match world.write().await.despawn_entity(entity_id) {
+                            Some(_) => println!("Despawned entity_id:{}", entity_id),
+                            None => println!("No entity with id {}", entity_id),
+                        }
+                    }
+                    _ => println!("usage: despawn <entity id> [world]"),
+                }
+            }
+            ("worlds", []) => {
+                for name in registry.names() {
+                    let world = registry.get(&name).unwrap();
+                    let players = world.read().await.players.len();
+                    let default = if name == registry.default_world {
+                        " (default)"
+                    } else {
+                        ""
+                    };
+                    println!("{}: {} players{}", name, players, default);
+                }
+            }
+            ("move", [client_id, world]) => {
+                let client = match client_id.parse::<u32>() {
+                    Ok(client_id) => client_manager.read().await.clients.get(&client_id).cloned(),
+                    Err(_) => None,
+                };
+                match (client, registry.get(world)) {
+                    (Some(client), Some(target)) => switch_world(&client, &target).await,
+                    (None, _) => println!("No player with id {}", client_id),
+                    (_, None) => println!("No world named {}", world),
+                }
+            }
+            _ => println!("Unknown command: {}", line),
+        }
+    }
+}
+
+// the named world, or the default world without a name. None if there is no such world
+fn console_world(registry: &WorldRegistry, name: Option<&&str>) -> Option<Arc<RwLock<World>>> {
+    match name {
+        Some(name) => registry.get(name),
+        None => Some(registry.default_world()),
+    }
+}
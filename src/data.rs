@@ -1,8 +1,16 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use crate::{client::Client};
+use crate::anticheat::validate_position;
+use crate::chunk::{VOXEL_AIR, VOXEL_ID_MAX};
+use crate::client::{Client, ClientManager};
+use crate::clock::{current_tick, server_time_ms};
+use crate::config::CONFIG;
 use crate::metrics::*;
+use crate::outbound::Priority;
+use crate::protocol::{ClientData, Input, Message};
+use crate::world::World;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(u8)]
 pub enum DataIdentifier {
     InitializeData = 0,
@@ -10,57 +18,262 @@ pub enum DataIdentifier {
     ChunkData = 2,
     Keepalive = 3,
     PlayerData = 4,
+    BlockPlace = 5,
+    BlockBreak = 6,
+    VoxelChanged = 7,
+    Hello = 8,
+    HelloResponse = 9,
+    PlayerEnterView = 10,
+    PlayerLeaveView = 11,
+    ChunkSectionData = 12,
+    ChunkUnload = 13,
+    Disconnect = 14,
+    UdpToken = 15,
+    Chat = 16,
+    TimeSync = 17,
+    Input = 18,
+    MovementCorrection = 19,
+    EntitySpawn = 20,
+    EntityUpdate = 21,
+    EntityDespawn = 22,
+    ChangeWorld = 23,
 }
 
+impl DataIdentifier {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(DataIdentifier::InitializeData),
+            1 => Some(DataIdentifier::ClientData),
+            2 => Some(DataIdentifier::ChunkData),
+            3 => Some(DataIdentifier::Keepalive),
+            4 => Some(DataIdentifier::PlayerData),
+            5 => Some(DataIdentifier::BlockPlace),
+            6 => Some(DataIdentifier::BlockBreak),
+            7 => Some(DataIdentifier::VoxelChanged),
+            8 => Some(DataIdentifier::Hello),
+            9 => Some(DataIdentifier::HelloResponse),
+            10 => Some(DataIdentifier::PlayerEnterView),
+            11 => Some(DataIdentifier::PlayerLeaveView),
+            12 => Some(DataIdentifier::ChunkSectionData),
+            13 => Some(DataIdentifier::ChunkUnload),
+            14 => Some(DataIdentifier::Disconnect),
+            15 => Some(DataIdentifier::UdpToken),
+            16 => Some(DataIdentifier::Chat),
+            17 => Some(DataIdentifier::TimeSync),
+            18 => Some(DataIdentifier::Input),
+            19 => Some(DataIdentifier::MovementCorrection),
+            20 => Some(DataIdentifier::EntitySpawn),
+            21 => Some(DataIdentifier::EntityUpdate),
+            22 => Some(DataIdentifier::EntityDespawn),
+            23 => Some(DataIdentifier::ChangeWorld),
+            _ => None,
+        }
+    }
+}
 
-// data procesing functions
-
-pub async fn process_client_data(data: Vec<u8>, client: Arc<RwLock<Client>>) {
-    // ensure data is correct length in bytes
-    let data_length = data.len();
-
-    // Read identifier (1 byte)
-    let identifier = data[0];
-
-    // Read client_id (next 4 bytes, little-endian)
-    let client_id = u32::from_le_bytes(data[1..5].try_into().unwrap());
+// how far (in voxels) from the player's position blocks can be placed or broken
+pub const MAX_REACH_DISTANCE: f32 = 8.0;
 
-    // Read position (3 x 4 bytes as f32, little-endian)
-    let pos_x = f32::from_le_bytes(data[5..9].try_into().unwrap());
-    let pos_y = f32::from_le_bytes(data[9..13].try_into().unwrap());
-    let pos_z = f32::from_le_bytes(data[13..17].try_into().unwrap());
-    
-    //read rotation y,z (2 pos_ 4 bytes as f32, little-endian)
-    let rotation_y = f32::from_le_bytes(data[17..21].try_into().unwrap());
-    let rotation_x = f32::from_le_bytes(data[21..25].try_into().unwrap());
 
-    // Read state (next 4 bytes, little-endian)
-    let state = u32::from_le_bytes(data[25..29].try_into().unwrap());
+// data procesing functions
 
-    let mut chunk_demand: Vec<(i32, i32, i32)> = Vec::new(); // temp vector to store chunk positions
-    // deserialize received chunks
-    for i in (29..data_length).step_by(12) {
-        if i + 8 > data_length {
-            continue;
+// client_id in the message is not used, the client is known from the connection
+pub async fn process_client_data(
+    client_data: ClientData,
+    client: Arc<RwLock<Client>>,
+    world: Arc<RwLock<World>>,
+) {
+    let ClientData {
+        position,
+        rotation_y,
+        rotation_x,
+        state,
+        mut chunk_demand,
+        ..
+    } = client_data;
+    // the position the server keeps: simulated with authoritative movement, else the reported one if it is valid
+    let (position, reported) = {
+        let world_guard = world.read().await;
+        let mut client = client.write().await;
+        // sent before the client moved to another world
+        if !Arc::ptr_eq(&client.world, &world) {
+            return;
         }
-        let x = i32::from_le_bytes(data[i..i + 4].try_into().unwrap());
-        let z = i32::from_le_bytes(data[i + 4..i + 8].try_into().unwrap());
-        let distance = i32::from_le_bytes(data[i + 8..i + 12].try_into().unwrap());
-        chunk_demand.push((x, z, distance));
-    }
+        if client.has_authoritative_movement() {
+            (client.position, false)
+        } else if client.is_spectator() {
+            // a spectator's camera goes anywhere within the world's bounds
+            (World::clamp_position(position), true)
+        } else {
+            (validate_position(&mut client, &world_guard, position), true)
+        }
+    };
+    // chunks outside the view distance are never sent
+    let (chunk_x, chunk_z) = World::chunk_coords_of(position);
+    // abs_diff, demanded coordinates come from the client and may be anything
+    let view_distance = i64::from(CONFIG.chunk_view_distance);
+    chunk_demand.retain(|&(x, z, _)| {
+        i64::from(x.abs_diff(chunk_x)) <= view_distance
+            && i64::from(z.abs_diff(chunk_z)) <= view_distance
+    });
+    // nearest chunks are queued first, chunks that are not generated yet get queued by world generation
+    chunk_demand.sort_by_key(|&(_, _, distance)| distance);
+    let generated_chunks: Vec<(i32, i32)> = {
+        let world = world.read().await;
+        chunk_demand
+            .iter()
+            .filter(|&&(x, z, _)| world.chunks.contains_key(&(x, z)))
+            .map(|&(x, z, _)| (x, z))
+            .collect()
+    };
     {
         let mut client = client.write().await;
-        client.position.0 = pos_x;
-        client.position.1 = pos_y;
-        client.position.2 = pos_z;
-        client.rotation.0 = rotation_x;
-        client.rotation.1 = rotation_y;
-        client.rotation.2 = 0.0;
+        if !Arc::ptr_eq(&client.world, &world) {
+            return;
+        }
+        if reported {
+            client.position = position;
+            client.rotation.0 = rotation_x;
+            client.rotation.1 = rotation_y;
+            client.rotation.2 = 0.0;
+        }
         client.state = state;
         client.chunk_demand = chunk_demand;
-        if client.packet_count_rx == 0 {}
+        client.unload_distant_chunks(CONFIG.chunk_view_distance);
+        for (x, z) in generated_chunks {
+            client.request_chunk(x, z);
+        }
         client.packet_count_rx += 1;
-        //metrics
-        NETWORK_BYTES_INGRESS_TOTAL.inc_by(data_length as u64);
+    }
+}
+
+// handles a keepalive echo and updates the client's round trip time
+pub async fn process_keepalive(seq: u32, client: Arc<RwLock<Client>>) {
+    let mut client = client.write().await;
+    match client.keepalive_sent {
+        Some((sent_seq, sent_at)) if sent_seq == seq => {
+            let sample = sent_at.elapsed().as_secs_f64() * 1000.0;
+            // smoothed like TCP's srtt, first sample is taken as is
+            client.rtt_ms = if client.rtt_ms == 0.0 {
+                sample
+            } else {
+                client.rtt_ms * 0.875 + sample * 0.125
+            };
+            client.keepalive_sent = None;
+            //metrics
+            CLIENT_RTT.observe(sample);
+        }
+        // late or unknown echo, the connection is alive but there is no usable sample
+        _ => {}
+    }
+}
+
+// queues an input for the next world update, false if the client doesn't use authoritative movement
+pub async fn process_input(input: Input, client: &Arc<RwLock<Client>>) -> bool {
+    let mut client = client.write().await;
+    if !client.has_authoritative_movement() {
+        return false;
+    }
+    client.movement.queue_input(input);
+    client.packet_count_rx += 1;
+    true
+}
+
+// answers a time sync request right away, queued as control so it isn't delayed behind bulk data
+pub async fn process_time_sync(client_time: u64, client: Arc<RwLock<Client>>) {
+    let data = Message::TimeSync {
+        client_time,
+        server_time: server_time_ms(),
+        tick: current_tick(),
+    }
+    .encode();
+    client.read().await.outbound.push_data(Priority::Control, data);
+}
+
+// handles BlockPlace and BlockBreak
+pub async fn process_block_data(
+    edit: Message,
+    client: Arc<RwLock<Client>>,
+    world: Arc<RwLock<World>>,
+    client_manager: Arc<RwLock<ClientManager>>,
+) {
+    // breaking is placing air
+    let (x, y, z, new_id, is_place) = match edit {
+        Message::BlockPlace { x, y, z, id } => (x, y, z, id, true),
+        Message::BlockBreak { x, y, z } => (x, y, z, VOXEL_AIR, false),
+        _ => return,
+    };
+
+    let client_id = client.read().await.id;
+    // collect clients before locking world so lock order stays world -> client
+    let clients: Vec<Arc<RwLock<Client>>> = {
+        let manager = client_manager.read().await;
+        manager.clients.values().cloned().collect()
+    };
+
+    // world write lock is held until the change is queued for every client,
+    // so a chunk can't be serialized with the old voxel and miss the delta
+    let world_arc = world.clone();
+    let mut world = world.write().await;
+    let Some(((chunk_x, chunk_z), _)) = World::world_to_chunk_coords(x, y, z) else {
+        println!("Block edit out of bounds ({},{},{})", x, y, z);
+        return;
+    };
+    let Some(current_id) = world.get_voxel_at(x, y, z) else {
+        println!("Block edit in unloaded chunk ({},{})", chunk_x, chunk_z);
+        return;
+    };
+
+    let in_reach = match world.get_player(client_id) {
+        Some(player) => {
+            let dx = player.position.0 - (x as f32 + 0.5);
+            let dy = player.position.1 - (y as f32 + 0.5);
+            let dz = player.position.2 - (z as f32 + 0.5);
+            (dx * dx + dy * dy + dz * dz).sqrt() <= MAX_REACH_DISTANCE
+        }
+        None => false,
+    };
+    let valid = if is_place {
+        // place only into air with a known solid voxel id
+        current_id == VOXEL_AIR && new_id != VOXEL_AIR && new_id <= VOXEL_ID_MAX
+    } else {
+        // break only solid voxels
+        current_id != VOXEL_AIR
+    };
+
+    if !in_reach || !valid {
+        println!(
+            "Rejected block edit from client_id:{} at ({},{},{}) id:{}",
+            client_id, x, y, z, new_id
+        );
+        // resync the voxel on the sender so its prediction gets reverted
+        let client = client.read().await;
+        if client.wants_voxel_updates(chunk_x, chunk_z) {
+            let data = Message::VoxelChanged {
+                x,
+                y,
+                z,
+                id: current_id,
+            }
+            .encode();
+            client.outbound.push_data(Priority::VoxelDelta, data);
+        }
+        return;
+    }
+
+    world.set_voxel_at(x, y, z, new_id);
+    // clients in other worlds may hold a chunk at the same coordinates
+    for client_arc in clients {
+        let client = client_arc.read().await;
+        if Arc::ptr_eq(&client.world, &world_arc) && client.wants_voxel_updates(chunk_x, chunk_z) {
+            let data = Message::VoxelChanged {
+                x,
+                y,
+                z,
+                id: new_id,
+            }
+            .encode();
+            client.outbound.push_data(Priority::VoxelDelta, data);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,169 @@
+// src/entity.rs
+// Non-player entities (dropped items, projectiles, NPCs, vehicles) living in World.entities.
+// They are spawned and despawned through the World API and moved every world update. Clients with
+// CAPABILITY_ENTITIES get EntitySpawn when an entity comes within CONFIG.entity_view_radius chunks,
+// an EntityUpdate snapshot of every visible entity each tick and EntityDespawn when it leaves view or is removed.
+
+use crate::client::Client;
+use crate::config::CONFIG;
+use crate::handshake::CAPABILITY_ENTITIES;
+use crate::movement::{is_solid, GRAVITY, TERMINAL_VELOCITY};
+use crate::outbound::Priority;
+use crate::protocol::Message;
+use crate::world::World;
+use serde::{Deserialize, Serialize};
+
+const GROUND_FRICTION: f32 = 0.5; // horizontal velocity kept per tick while resting on a voxel
+const MIN_VELOCITY: f32 = 0.01; // slower than this counts as standing still
+const MAX_SUBSTEP: f32 = 0.5; // longest move checked for a voxel at once
+const SKIN: f32 = 0.001; // gap kept below or beside a voxel face, an entity on top rests exactly on it
+
+// id, position and velocity of an entity in an EntityUpdate
+pub type EntityState = (u32, (f32, f32, f32), (f32, f32, f32));
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum EntityKind {
+    Item = 0,       // dropped item, data is the voxel id
+    Projectile = 1, // removed when it hits a voxel
+    Npc = 2,
+    Vehicle = 3,
+}
+
+impl EntityKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(EntityKind::Item),
+            1 => Some(EntityKind::Projectile),
+            2 => Some(EntityKind::Npc),
+            3 => Some(EntityKind::Vehicle),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "item" => Some(EntityKind::Item),
+            "projectile" => Some(EntityKind::Projectile),
+            "npc" => Some(EntityKind::Npc),
+            "vehicle" => Some(EntityKind::Vehicle),
+            _ => None,
+        }
+    }
+
+    pub fn has_gravity(&self) -> bool {
+        matches!(self, EntityKind::Item | EntityKind::Projectile)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Entity {
+    pub id: u32,
+    pub kind: EntityKind,
+    pub position: (f32, f32, f32),
+    pub velocity: (f32, f32, f32), // blocks per second
+    pub data: Vec<u8>,             // kind specific data, sent to clients with the spawn
+    pub expires_at: Option<u64>,   // tick the entity is removed at, server only
+}
+
+impl Entity {
+    // moves the entity by one tick, returns false when it should be removed
+    pub fn update(&mut self, world: &World, tick: u64, dt: f32) -> bool {
+        if self.expires_at.is_some_and(|expires_at| tick >= expires_at) {
+            return false;
+        }
+        if self.kind.has_gravity() {
+            self.velocity.1 = (self.velocity.1 - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+        }
+
+        // entities are points, each axis stops at the first solid voxel
+        let mut position = [self.position.0, self.position.1, self.position.2];
+        let mut velocity = [self.velocity.0, self.velocity.1, self.velocity.2];
+        let mut hit = false;
+        for (axis, velocity) in velocity.iter_mut().enumerate() {
+            if move_axis(world, &mut position, axis, *velocity * dt) {
+                *velocity = 0.0;
+                hit = true;
+            }
+        }
+        if hit && self.kind == EntityKind::Projectile {
+            return false;
+        }
+        let on_ground = is_solid(
+            world,
+            position[0].floor() as i32,
+            (position[1] - MIN_VELOCITY).floor() as i32,
+            position[2].floor() as i32,
+        );
+        if on_ground {
+            velocity[0] *= GROUND_FRICTION;
+            velocity[2] *= GROUND_FRICTION;
+        }
+        for value in velocity.iter_mut() {
+            if value.abs() < MIN_VELOCITY {
+                *value = 0.0;
+            }
+        }
+        self.position = (position[0], position[1], position[2]);
+        self.velocity = (velocity[0], velocity[1], velocity[2]);
+        true
+    }
+}
+
+// moves a point along one axis in steps shorter than a voxel so fast entities can't pass through one,
+// ending against the face of the voxel that blocked. returns true if blocked
+fn move_axis(world: &World, position: &mut [f32; 3], axis: usize, delta: f32) -> bool {
+    if delta == 0.0 {
+        return false;
+    }
+    let steps = (delta.abs() / MAX_SUBSTEP).ceil();
+    let step = delta / steps;
+    for _ in 0..steps as u32 {
+        let mut next = *position;
+        next[axis] += step;
+        if is_solid(
+            world,
+            next[0].floor() as i32,
+            next[1].floor() as i32,
+            next[2].floor() as i32,
+        ) {
+            position[axis] = if step > 0.0 {
+                next[axis].floor() - SKIN
+            } else {
+                next[axis].floor() + 1.0
+            };
+            return true;
+        }
+        *position = next;
+    }
+    false
+}
+
+// sends entity spawns, despawns and the entity snapshot to a client, called every world update
+pub fn replicate_entities(world: &World, client: &mut Client, tick: u64) {
+    if client.capabilities & CAPABILITY_ENTITIES == 0 {
+        return;
+    }
+    let visible_entities = world.entities_in_range(client.position, CONFIG.entity_view_radius);
+    for &entity_id in visible_entities.difference(&client.visible_entities) {
+        if let Some(entity) = world.entities.get(&entity_id) {
+            let data = Message::EntitySpawn {
+                entity: entity.clone(),
+            }
+            .encode();
+            client.outbound.push_data(Priority::Entity, data);
+        }
+    }
+    for &entity_id in client.visible_entities.difference(&visible_entities) {
+        let data = Message::EntityDespawn { entity_id }.encode();
+        client.outbound.push_data(Priority::Entity, data);
+    }
+    if !visible_entities.is_empty() {
+        // over UDP when bound, otherwise replaces an unsent older snapshot
+        let entity_data = world.entities_to_bytes(&visible_entities, tick);
+        if !client.send_unreliable(&entity_data) {
+            client.outbound.push_latest(Priority::Entity, entity_data);
+        }
+    }
+    client.visible_entities = visible_entities;
+}
@@ -0,0 +1,83 @@
+// src/handshake.rs
+// Hello exchange that runs before InitializeData is sent.
+// The client sends Hello with its protocol version, capabilities and optionally a session token to resume,
+// the server answers with HelloResponse (layouts in protocol.rs).
+// A client announcing CAPABILITY_SPECTATOR connects as a spectator: a free camera without a player body.
+// It streams chunks and gets player and entity snapshots around the position it reports, but it is not
+// one of the world's players, doesn't count toward CONFIG.max_players and can't edit the world.
+
+use crate::config::CONFIG;
+use crate::protocol::{Hello, Message};
+
+// bump whenever the layout of any message changes
+pub const PROTOCOL_VERSION: u16 = 4;
+
+// capability flags, a capability is enabled only when both sides announce it
+pub const CAPABILITY_VOXEL_UPDATES: u32 = 1 << 0; // client understands VoxelChanged messages
+pub const CAPABILITY_COMPRESSION_DEFLATE: u32 = 1 << 1; // client can inflate chunk payloads
+pub const CAPABILITY_COMPRESSION_ZSTD: u32 = 1 << 2; // client can decompress zstd chunk payloads
+pub const CAPABILITY_CHUNK_SECTIONS: u32 = 1 << 3; // client wants ChunkSectionData instead of ChunkData
+pub const CAPABILITY_UDP: u32 = 1 << 4; // client wants movement and player snapshots over UDP
+pub const CAPABILITY_AUTHORITATIVE_MOVEMENT: u32 = 1 << 5; // client sends Input frames, see movement.rs
+pub const CAPABILITY_ENTITIES: u32 = 1 << 6; // client wants non-player entities, see entity.rs
+pub const CAPABILITY_SPECTATOR: u32 = 1 << 7; // client observes without a player body
+
+pub const SERVER_CAPABILITIES: u32 = CAPABILITY_VOXEL_UPDATES
+    | CAPABILITY_COMPRESSION_DEFLATE
+    | CAPABILITY_COMPRESSION_ZSTD
+    | CAPABILITY_CHUNK_SECTIONS
+    | CAPABILITY_UDP
+    | CAPABILITY_AUTHORITATIVE_MOVEMENT
+    | CAPABILITY_ENTITIES
+    | CAPABILITY_SPECTATOR;
+
+// decodes and checks the first frame of a connection, Err holds the reason sent back to the client
+pub fn parse_hello(data: &[u8]) -> Result<Hello, String> {
+    let hello = match Message::decode(data) {
+        Ok(Message::Hello(hello)) => hello,
+        Ok(message) => {
+            return Err(format!(
+                "expected hello, got identifier {}",
+                message.identifier() as u8
+            ))
+        }
+        Err(e) => return Err(format!("invalid hello: {}", e)),
+    };
+    if hello.protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "unsupported protocol version {}, server speaks {}",
+            hello.protocol_version, PROTOCOL_VERSION
+        ));
+    }
+    // positions from clients are not trusted, so clients that can only send them are turned away.
+    // a spectator's position is only its camera
+    if CONFIG.authoritative_movement
+        && hello.capabilities & (CAPABILITY_AUTHORITATIVE_MOVEMENT | CAPABILITY_SPECTATOR) == 0
+    {
+        return Err("server requires authoritative movement".to_string());
+    }
+    Ok(hello)
+}
+
+// capabilities both the client and the server support
+pub fn negotiate_capabilities(client_capabilities: u32) -> u32 {
+    let mut capabilities = client_capabilities & SERVER_CAPABILITIES;
+    if CONFIG.udp_addr.is_empty() {
+        capabilities &= !CAPABILITY_UDP;
+    }
+    // a spectator's camera moves freely, there is no body to simulate
+    if !CONFIG.authoritative_movement || capabilities & CAPABILITY_SPECTATOR != 0 {
+        capabilities &= !CAPABILITY_AUTHORITATIVE_MOVEMENT;
+    }
+    capabilities
+}
+
+pub fn hello_response_to_bytes(accepted: bool, capabilities: u32, reason: &str) -> Vec<u8> {
+    Message::HelloResponse {
+        accepted,
+        protocol_version: PROTOCOL_VERSION,
+        capabilities,
+        reason: reason.to_string(),
+    }
+    .encode()
+}
@@ -0,0 +1,27 @@
+// src/lib.rs
+// The server's modules as a library, so a Rust test client or tool can share the wire protocol with the server:
+// protocol (typed messages), codec (framing) and data::DataIdentifier. main.rs runs the server on top of it.
+
+pub mod anticheat;
+pub mod chat;
+pub mod chunk;
+pub mod client;
+pub mod clock;
+pub mod codec;
+pub mod compression;
+pub mod config;
+pub mod console;
+pub mod data;
+pub mod entity;
+pub mod handshake;
+pub mod metrics;
+pub mod movement;
+pub mod outbound;
+pub mod protocol;
+pub mod region;
+pub mod registry;
+pub mod session;
+pub mod tls;
+pub mod transport;
+pub mod udp;
+pub mod world;
@@ -1,51 +1,108 @@
 // src/main.rs
-mod chunk;
-mod client;
-mod data;
-mod metrics;
-mod world;
 
-use client::{Client, ClientManager};
-use data::{process_client_data, DataIdentifier};
-use futures_util::stream::StreamExt;
-use futures_util::SinkExt;
-use metrics::*;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::thread::Thread;
 use std::time::Instant;
 use std::vec;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::net::TcpListener;
-use tokio::sync::{Mutex, RwLock};
-use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
-use world::{Player, World};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{Mutex, Notify, RwLock};
+use voxel_server::anticheat::MovementCheck;
+use voxel_server::chat::{broadcast_system, process_chat, send_system_to};
+use voxel_server::client::{Client, ClientManager};
+use voxel_server::clock::{self, current_tick};
+use voxel_server::codec::{FrameEncoder, FrameError, LENGTH_HEADER_SIZE};
+use voxel_server::compression::Compression;
+use voxel_server::config::CONFIG;
+use voxel_server::console::console_task;
+use voxel_server::data::{
+    process_block_data, process_client_data, process_input, process_keepalive, process_time_sync,
+};
+use voxel_server::handshake::{
+    hello_response_to_bytes, negotiate_capabilities, parse_hello, CAPABILITY_CHUNK_SECTIONS,
+    CAPABILITY_SPECTATOR, CAPABILITY_UDP,
+};
+use voxel_server::metrics::{self, *};
+use voxel_server::movement::Movement;
+use voxel_server::outbound::{OutboundMessage, OutboundQueue, Priority};
+use voxel_server::protocol::{Hello, Message};
+use voxel_server::registry::{switch_world, WorldRegistry};
+use voxel_server::session::{
+    disconnect_all, disconnect_client, record_violation, resume_session, DisconnectReason,
+};
+use voxel_server::tls::{accept_stream, tls_reload_task, TlsConfig};
+use voxel_server::transport::{websocket_config, FrameReader, FrameWriter, Incoming};
+use voxel_server::udp::udp_receive_task;
+use voxel_server::world::{Player, World};
 
 #[tokio::main]
 async fn main() {
-    let world = Arc::new(RwLock::new(World::new()));
+    clock::start();
+    // a world configuration that can't be used stops the server
+    let registry = Arc::new(WorldRegistry::from_config(&CONFIG.worlds).unwrap_or_else(|e| {
+        panic!("Invalid world configuration: {}", e);
+    }));
     let client_manager = Arc::new(RwLock::new(ClientManager::new()));
 
+    // optional TLS, a configured certificate that can't be loaded stops the server
+    let tls = if CONFIG.tls_cert_path.is_empty() {
+        None
+    } else {
+        let tls = Arc::new(TlsConfig::load().unwrap_or_else(|e| {
+            panic!("Failed to load TLS certificate: {}", e);
+        }));
+        tokio::spawn(tls_reload_task(tls.clone()));
+        Some(tls)
+    };
+    let tcp_tls = tls.clone().filter(|_| CONFIG.tls_tcp);
+    let ws_tls = tls.filter(|_| CONFIG.tls_websocket);
+
     // Set up TCP & Websocket listener for client connections
     let addr = "127.0.0.1:6969";
     let listener = TcpListener::bind(addr).await.unwrap();
-    println!("Server running on {}", addr);
+    println!(
+        "Server running on {}{}",
+        addr,
+        if tcp_tls.is_some() { " (tls)" } else { "" }
+    );
 
     let ws_addr = "127.0.0.1:6970"; // WebSocket port
     let ws_listener = TcpListener::bind(ws_addr).await.unwrap();
-    println!("WebSocket server running on {}", ws_addr);
+    println!(
+        "WebSocket server running on {}{}",
+        ws_addr,
+        if ws_tls.is_some() { " (wss)" } else { "" }
+    );
+
+    // optional UDP channel for movement
+    if !CONFIG.udp_addr.is_empty() {
+        let udp_socket = Arc::new(UdpSocket::bind(&CONFIG.udp_addr).await.unwrap());
+        println!("UDP channel running on {}", CONFIG.udp_addr);
+        tokio::spawn(udp_receive_task(udp_socket, client_manager.clone()));
+    }
+
+    // admin commands on stdin
+    tokio::spawn(console_task(registry.clone(), client_manager.clone()));
 
     // start metrics endpoint
     tokio::spawn(metrics::start());
-    // Start generating chunks in a separate task
-    tokio::spawn(World::world_generation_task(
-        world.clone(),
-        client_manager.clone(),
-    ));
+    // Start generating chunks in a separate task per world
+    for world in registry.worlds.values() {
+        tokio::spawn(World::world_generation_task(
+            world.clone(),
+            client_manager.clone(),
+        ));
+    }
+    // save modified chunks of every world
+    if !CONFIG.world_dir.is_empty() {
+        println!("Saving worlds to {}", CONFIG.world_dir);
+        for world in registry.worlds.values() {
+            tokio::spawn(World::world_save_task(world.clone(), CONFIG.save_interval_ms));
+        }
+    }
     // start world update task
     tokio::spawn(World::world_update_task(
-        world.clone(),
+        registry.clone(),
         client_manager.clone(),
         100, // 10/s
     ));
@@ -54,39 +111,57 @@ async fn main() {
     tokio::spawn(accept_connections(
         listener,
         ws_listener,
+        tcp_tls,
+        ws_tls,
         client_manager.clone(),
-        world.clone(),
+        registry.clone(),
     ));
 
     // Keep the server running indefinitely
     tokio::signal::ctrl_c().await.unwrap();
     println!("Server shutting down");
+    disconnect_all(&client_manager).await;
+    for world in registry.worlds.values() {
+        World::save_modified_chunks(world).await;
+    }
+    // give writer tasks a moment to deliver the disconnect messages
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 }
 
 async fn accept_connections(
     listener: TcpListener,
     ws_listener: TcpListener,
+    tcp_tls: Option<Arc<TlsConfig>>,
+    ws_tls: Option<Arc<TlsConfig>>,
     client_manager: Arc<RwLock<ClientManager>>,
-    world: Arc<RwLock<World>>,
+    registry: Arc<WorldRegistry>,
 ) {
     // Spawn a task to handle TCP connections
-    let tcp_listener_task = tokio::spawn(async move {
+    let tcp_client_manager = client_manager.clone();
+    let tcp_registry = registry.clone();
+    tokio::spawn(async move {
         loop {
             match listener.accept().await {
                 Ok((stream, _)) => {
                     println!("TCP Client connected!");
-
-                    let (read_half, write_half) = stream.into_split();
-                    let write_half = Arc::new(Mutex::new(write_half));
-
-                    // Handle the new TCP connection
-                    handle_new_connection(
-                        read_half,
-                        write_half.clone(),
-                        client_manager.clone(),
-                        world.clone(),
-                    )
-                    .await;
+                    let client_manager = tcp_client_manager.clone();
+                    let registry = tcp_registry.clone();
+                    let tls = tcp_tls.clone();
+                    // TLS handshake in its own task so a slow client doesn't block accepting
+                    tokio::spawn(async move {
+                        let stream = match accept_stream(stream, tls.as_deref()).await {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                eprintln!("Error during TLS handshake: {:?}", e);
+                                return;
+                            }
+                        };
+                        let (reader, writer) = FrameReader::tcp(stream, CONFIG.max_frame_size);
+                        let writer = Arc::new(Mutex::new(writer));
+
+                        // Handle the new TCP connection
+                        handle_new_connection(reader, writer, client_manager, registry).await;
+                    });
                 }
                 Err(e) => {
                     eprintln!("Failed to accept TCP connection: {:?}", e);
@@ -95,18 +170,43 @@ async fn accept_connections(
         }
     });
     // Spawn a task to handle WebSocket connections
-    let ws_listener_task = tokio::spawn(async move {
+    tokio::spawn(async move {
         loop {
             match ws_listener.accept().await {
                 Ok((stream, _)) => {
                     println!("WebSocket Client connected!");
-                    // Handle the WebSocket connection
-                    let ws_stream = tokio_tungstenite::accept_async(stream)
+                    let client_manager = client_manager.clone();
+                    let registry = registry.clone();
+                    let tls = ws_tls.clone();
+                    // upgrade in its own task so a slow handshake doesn't block accepting
+                    tokio::spawn(async move {
+                        let stream = match accept_stream(stream, tls.as_deref()).await {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                eprintln!("Error during TLS handshake: {:?}", e);
+                                return;
+                            }
+                        };
+                        let config = websocket_config(CONFIG.max_frame_size);
+                        let ws_stream = match tokio_tungstenite::accept_async_with_config(
+                            stream,
+                            Some(config),
+                        )
                         .await
-                        .expect("Error during WebSocket handshake");
-
-                    // Spawn a task to handle the WebSocket communication
-                    tokio::spawn(handle_websocket(ws_stream));
+                        {
+                            Ok(ws_stream) => ws_stream,
+                            Err(e) => {
+                                eprintln!("Error during WebSocket handshake: {:?}", e);
+                                return;
+                            }
+                        };
+                        let (reader, writer) =
+                            FrameReader::websocket(ws_stream, CONFIG.max_frame_size);
+                        let writer = Arc::new(Mutex::new(writer));
+
+                        // WebSocket clients go through the same session logic as TCP clients
+                        handle_new_connection(reader, writer, client_manager, registry).await;
+                    });
                 }
                 Err(e) => {
                     eprintln!("Failed to accept WebSocket connection: {:?}", e);
@@ -117,227 +217,455 @@ async fn accept_connections(
 }
 
 async fn handle_new_connection(
-    read_half: OwnedReadHalf,
-    write_half: Arc<Mutex<OwnedWriteHalf>>,
+    mut reader: FrameReader,
+    writer: Arc<Mutex<FrameWriter>>,
     client_manager: Arc<RwLock<ClientManager>>,
-    world: Arc<RwLock<World>>,
+    registry: Arc<WorldRegistry>,
 ) {
-    // Assign a new client ID by locking client_manager
-    let client_id = {
-        let manager = client_manager.read().await;
-        (manager.clients.len() as u32) + 1
+    // wait for the hello before anything else is sent
+    let hello = match tokio::time::timeout(
+        std::time::Duration::from_millis(CONFIG.handshake_timeout_ms),
+        reader.read_frame(),
+    )
+    .await
+    {
+        Ok(Ok(Some(frame))) => parse_hello(&frame),
+        Ok(Ok(None)) => return,
+        Ok(Err(e)) => Err(format!("invalid frame: {}", e)),
+        Err(_) => Err("handshake timed out".to_string()),
     };
-    // get spawn point coordinates
-    let spawn_point = {
-        let world = world.read().await;
-        (
-            world.spawn.0 as f32,
-            world.spawn.1 as f32,
-            world.spawn.2 as f32,
-        )
+    let hello = match hello {
+        Ok(hello) if !has_player_slot(&hello, &client_manager).await => {
+            Err("server is full".to_string())
+        }
+        hello => hello,
+    };
+    let hello = match hello {
+        Ok(hello) => hello,
+        Err(reason) => {
+            println!("Handshake rejected: {}", reason);
+            send_data(writer.clone(), hello_response_to_bytes(false, 0, &reason)).await;
+            writer.lock().await.close().await;
+            return;
+        }
+    };
+    let capabilities = negotiate_capabilities(hello.capabilities);
+    let is_spectator = capabilities & CAPABILITY_SPECTATOR != 0;
+    if !send_data(writer.clone(), hello_response_to_bytes(true, capabilities, "")).await {
+        return;
+    }
+
+    // resume a lost session when the client presents its token, spectators always start a new one
+    let resumed = match hello.session_token {
+        Some(session_token) if !is_spectator => {
+            resume_session(session_token, &client_manager).await
+        }
+        _ => None,
+    };
+    // Assign a new client ID and session token by locking client_manager
+    let (client_id, session_token) = match &resumed {
+        Some(session) => (session.client_id, session.session_token),
+        None => {
+            let mut manager = client_manager.write().await;
+            let client_id = manager.allocate_client_id();
+            (client_id, manager.issue_session_token(client_id))
+        }
     };
+    // new players start at the spawn of the default world, a resumed session goes back to its world
+    let world = match &resumed {
+        Some(session) => session.world.clone(),
+        None => registry.default_world(),
+    };
+    // get spawn point coordinates
+    let spawn_point = world.read().await.spawn_point();
 
     // Create the new client object
-    let client = Arc::new(RwLock::new(Client {
+    let mut client = Client {
         id: client_id,
+        session_token,
         position: spawn_point,
         rotation: (0.0, 0.0, 0.0),
         state: 0,
+        capabilities,
         chunk_demand: vec![],
+        loaded_chunks: HashSet::new(),
+        queued_chunks: HashSet::new(),
+        sending_chunks: HashSet::new(),
+        visible_players: HashSet::new(),
+        visible_entities: HashSet::new(),
+        outbound: Arc::new(OutboundQueue::new()),
+        shutdown: Arc::new(Notify::new()),
+        last_seen: Instant::now(),
+        synced_tick: current_tick(),
+        keepalive_seq: 0,
+        keepalive_sent: None,
+        rtt_ms: 0.0,
+        udp: None,
+        chat_sent: VecDeque::new(),
+        violations: 0,
+        movement: Movement::new(),
+        movement_check: MovementCheck::new(spawn_point),
+        world: world.clone(),
         packet_count_rx: 0,
-    }));
-    println!("New client created");
+    };
+    let is_resumed = resumed.is_some();
+    match resumed {
+        Some(session) => {
+            println!("Session of client_id:{} resumed", client_id);
+            let world = world.read().await;
+            session.restore(&mut client, &world);
+        }
+        None => println!(
+            "New client created (protocol v{}, capabilities {:#x})",
+            hello.protocol_version, capabilities
+        ),
+    }
+    let client = Arc::new(RwLock::new(client));
     //metrics
     CLIENT_COUNT.inc();
+    // this is for initializing the client
+    {
+        let client = client.read().await;
+        client
+            .outbound
+            .push_data(Priority::Control, client.client_to_bytes());
+        let name = world.read().await.name.clone();
+        client.outbound.push_data(
+            Priority::Control,
+            Message::ChangeWorld {
+                name,
+                position: client.position,
+            }
+            .encode(),
+        );
+    }
     // Add the client to the manager
     {
         let mut manager = client_manager.write().await;
         manager.add_client(client.clone()).await;
+        if capabilities & CAPABILITY_UDP != 0 {
+            let port = CONFIG
+                .udp_addr
+                .parse::<SocketAddr>()
+                .map(|addr| addr.port())
+                .unwrap_or(0);
+            let token = manager.issue_udp_token(client_id);
+            let client = client.read().await;
+            client
+                .outbound
+                .push_data(Priority::Control, Message::UdpToken { token, port }.encode());
+        }
     }
-    // add player to world
-    {
-        let mut world = world.write().await;
-        world.add_player(Player::new(
-            client_id,
-            (0.0, 102.0, 0.0),
-            (0.0, 0.0, 0.0),
-            0,
-        ));
+    // add player to world, unless it was already moved to another one. spectators have no player
+    if !is_spectator {
+        let world_arc = world;
+        let mut world = world_arc.write().await;
+        let client = client.read().await;
+        if Arc::ptr_eq(&client.world, &world_arc) {
+            world.add_player(Player::new(
+                client_id,
+                client.position,
+                client.rotation,
+                client.state,
+            ));
+        }
+    }
+    if !is_resumed && !is_spectator {
+        broadcast_system(&format!("player {} joined", client_id), &client_manager).await;
     }
 
-    // Spawn a task to handle incoming data (read_half) and outgoing data (write_half)
+    // Spawn a task to handle incoming data (reader) and outgoing data (writer)
     tokio::spawn(handle_rx(
-        read_half,
+        reader,
         client.clone(),
-        world.clone(),
-        client_manager,
+        registry,
+        client_manager.clone(),
     ));
-    tokio::spawn(handle_tx(write_half, client.clone(), world.clone()));
+    tokio::spawn(handle_tx(writer, client.clone()));
+    tokio::spawn(handle_keepalive(client.clone(), client_manager));
 }
 
-const BUFFER_SIZE: usize = 1024; // Adjust buffer size as needed
-const LENGTH_BUFFER_SIZE: usize = 4; // Adjust buffer size as needed
+// spectators and clients taking back a session of a player don't need a free slot
+async fn has_player_slot(hello: &Hello, client_manager: &Arc<RwLock<ClientManager>>) -> bool {
+    if CONFIG.max_players == 0 || hello.capabilities & CAPABILITY_SPECTATOR != 0 {
+        return true;
+    }
+    let manager = client_manager.read().await;
+    let returning_player = hello.session_token.is_some_and(|token| {
+        manager
+            .sessions
+            .get(&token)
+            .is_some_and(|client_id| !manager.spectators.contains(client_id))
+    });
+    returning_player || manager.player_count() < CONFIG.max_players
+}
 
 async fn handle_rx(
-    mut read_half: OwnedReadHalf,
+    mut reader: FrameReader,
     client: Arc<RwLock<Client>>,
-    world: Arc<RwLock<World>>,
+    registry: Arc<WorldRegistry>,
     client_manager: Arc<RwLock<ClientManager>>,
 ) {
-    let mut length_buffer = [0u8; LENGTH_BUFFER_SIZE];
-    loop {
-        // read length header
-        if let Err(e) = read_half.read(&mut length_buffer).await {
-            println!(
-                "Error reading length header: {} {:?}",
-                e,
-                &length_buffer[..length_buffer.len().min(16)]
-            );
-            return;
-        }
+    let shutdown = client.read().await.shutdown.clone();
+    let (reason, message) = loop {
+        let incoming = tokio::select! {
+            incoming = reader.read() => incoming,
+            // disconnected from somewhere else, cleanup already ran
+            _ = shutdown.notified() => return,
+        };
+        let received_data = match incoming {
+            Ok(Incoming::Frame(frame)) => Some(frame),
+            // websocket pings only count as activity
+            Ok(Incoming::Ping) => None,
+            Ok(Incoming::Closed) => {
+                break (DisconnectReason::Quit, "connection closed by client".to_string())
+            }
+            Ok(Incoming::Ended) => {
+                break (DisconnectReason::ConnectionLost, "connection closed".to_string())
+            }
+            Err(FrameError::Io(e)) => break (DisconnectReason::ConnectionLost, e.to_string()),
+            Err(e) => break (DisconnectReason::ProtocolError, format!("invalid frame: {}", e)),
+        };
+
+        client.write().await.last_seen = Instant::now();
+        let Some(received_data) = received_data else {
+            continue;
+        };
 
-        // length_buffer to int
-        let total_message_length = u32::from_le_bytes(length_buffer) as usize;
+        //metrics
+        NETWORK_BYTES_INGRESS_TOTAL.inc_by((received_data.len() + LENGTH_HEADER_SIZE) as u64);
         println!(
-            "Total message length (including length header): {}",
-            total_message_length
+            "Full data received: Identifier:{} ({} bytes) ↓ ",
+            received_data[0],
+            received_data.len() + LENGTH_HEADER_SIZE
         );
-
-        let mut received_data = vec![0u8; total_message_length]; // data - 4-byte length
-        let mut total_bytes_read = 0;
-
-        // read for the amount length_buffer says in 1024 byte chunks
-        while total_bytes_read < total_message_length - LENGTH_BUFFER_SIZE {
-            let bytes_to_read =
-                std::cmp::min(BUFFER_SIZE, total_message_length - 4 - total_bytes_read);
-            match read_half
-                .read(&mut received_data[total_bytes_read..total_bytes_read + bytes_to_read])
-                .await
-            {
-                Ok(bytes_read) => {
-                    if bytes_read == 0 {
-                        let client_id = client.read().await.id;
-                        //remove client from client_manager
-                        let mut manager = client_manager.write().await;
-                        manager.remove_client(client_id);
-                        eprintln!("Connection closed or read error on client_id:{}", client_id);
-                        CLIENT_COUNT.dec();
-
-                        return; // or break; based on your logic
+        println!("Bytes{:?}", &received_data[..received_data.len().min(16)]);
+        let incoming = match Message::decode(&received_data) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                if record_violation(&client, e.kind(), &e.to_string()).await {
+                    break (DisconnectReason::Kicked, format!("invalid messages: {}", e));
+                }
+                continue;
+            }
+        };
+        // the world can change with every message
+        let world = client.read().await.world.clone();
+        // spawn tasks for processing data, messages whose order matters are processed in place
+        match incoming {
+            // inline, positions are validated against the previous one
+            Message::ClientData(client_data) => {
+                process_client_data(client_data, client.clone(), world).await;
+            }
+            Message::Keepalive { seq } => {
+                tokio::spawn(process_keepalive(seq, client.clone()));
+            }
+            // inline, the order of inputs matters
+            Message::Input(input) => {
+                if !process_input(input, &client).await
+                    && record_violation(&client, "unexpected", "input without authoritative movement")
+                        .await
+                {
+                    break (
+                        DisconnectReason::Kicked,
+                        "invalid messages: input without authoritative movement".to_string(),
+                    );
+                }
+            }
+            Message::TimeSync { client_time, .. } => {
+                tokio::spawn(process_time_sync(client_time, client.clone()));
+            }
+            Message::Disconnect { reason, message } => break (reason, message),
+            // processed in place so a player's messages keep their order
+            Message::Chat {
+                channel,
+                peer_id,
+                text,
+            } => {
+                process_chat(
+                    channel,
+                    peer_id,
+                    text,
+                    client.clone(),
+                    world,
+                    client_manager.clone(),
+                )
+                .await;
+            }
+            // processed in place so messages sent after it already go to the new world
+            Message::ChangeWorld { name, .. } => {
+                if !CONFIG.client_world_switch {
+                    send_system_to(&client, "changing worlds is not allowed").await;
+                } else {
+                    match registry.get(&name) {
+                        Some(target) => switch_world(&client, &target).await,
+                        None => {
+                            send_system_to(&client, &format!("no world named {}", name)).await
+                        }
                     }
-                    total_bytes_read += bytes_read; // Update the total bytes read
                 }
-                Err(e) => {
-                    // Handle the error accordingly
-                    eprintln!("Error reading data: {}", e);
-                    return;
+            }
+            edit @ (Message::BlockPlace { .. } | Message::BlockBreak { .. })
+                if client.read().await.is_spectator() =>
+            {
+                let detail = format!("{:?} from a spectator", edit.identifier());
+                if record_violation(&client, "unexpected", &detail).await {
+                    break (DisconnectReason::Kicked, format!("invalid messages: {}", detail));
+                }
+            }
+            edit @ (Message::BlockPlace { .. } | Message::BlockBreak { .. }) => {
+                tokio::spawn(process_block_data(
+                    edit,
+                    client.clone(),
+                    world,
+                    client_manager.clone(),
+                ));
+            }
+            // server to client messages
+            other => {
+                let detail = format!("unexpected {:?}", other.identifier());
+                if record_violation(&client, "unexpected", &detail).await {
+                    break (DisconnectReason::Kicked, format!("invalid messages: {}", detail));
                 }
             }
         }
+    };
 
-        // check if all data is received and process data based on identifier
-        if total_bytes_read == total_message_length - LENGTH_BUFFER_SIZE {
-            // get data identifier (1st byte)
-            let identifier = received_data[0] as u8;
-            println!(
-                "Full data received: Identifier:{} ({} bytes) ↓ ",
-                identifier,
-                total_bytes_read + LENGTH_BUFFER_SIZE
-            );
-            println!("Bytes{:?}", &received_data[..received_data.len().min(16)]);
-            // spawn tasks for processing data
-            match identifier {
-                1 => tokio::spawn(process_client_data(received_data, client.clone())),
-                2 => tokio::spawn(async {}),
-                3 => tokio::spawn(async { /*process keepalive*/ }),
-                _ => {
-                    println!("Invalid dentifier ({}) cannot process!", identifier);
-                    tokio::spawn(async {})
-                }
-            };
-        } else {
-            println!("Failed to read the full message.");
+    disconnect_client(&client, reason, &message, &client_manager).await;
+}
+
+// pings the client every keepalive interval and disconnects it when nothing was received for too long
+async fn handle_keepalive(
+    client: Arc<RwLock<Client>>,
+    client_manager: Arc<RwLock<ClientManager>>,
+) {
+    let timeout = std::time::Duration::from_millis(CONFIG.keepalive_timeout_ms);
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_millis(CONFIG.keepalive_interval_ms));
+    loop {
+        interval.tick().await;
+        let mut client_guard = client.write().await;
+        if client_guard.outbound.is_closed() {
+            break;
         }
-        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        if client_guard.last_seen.elapsed() > timeout {
+            drop(client_guard);
+            disconnect_client(
+                &client,
+                DisconnectReason::Timeout,
+                "keepalive timeout",
+                &client_manager,
+            )
+            .await;
+            break;
+        }
+        let client = &mut *client_guard;
+        client.keepalive_seq = client.keepalive_seq.wrapping_add(1);
+        let seq = client.keepalive_seq;
+        client.keepalive_sent = Some((seq, Instant::now()));
+        client
+            .outbound
+            .push_data(Priority::Control, Message::Keepalive { seq }.encode());
     }
 }
 
+// single writer task, drains the client's outbound queue
 async fn handle_tx(
-    write_half: Arc<Mutex<OwnedWriteHalf>>,
+    writer: Arc<Mutex<FrameWriter>>,
     client: Arc<RwLock<Client>>,
-    world: Arc<RwLock<World>>,
 ) {
-    {
-        // this is for initializing the client
-        let client_data = client.read().await.client_to_bytes();
-        send_data(write_half.clone(), client_data).await;
-    }
-
-    loop {
-        println!("LOOOP");
-        let mut remaining_chunks = Vec::new();
-        let chunk_demand = {
-            let client = client.read().await;
-            client.chunk_demand.clone()
-        };
-
-        // send chunks on demand
-        for chunk in chunk_demand {
-            let x = chunk.0;
-            let z = chunk.1;
-            //check if chunk is generated
-            let world = world.read().await;
-            //println!("debug {:?}", chunk);
-            if world.chunks.contains_key(&(x, z)) {
-                let chunk_data = world.chunk_to_bytes_rle(chunk.0, chunk.1);
-                if !send_data(write_half.clone(), chunk_data).await {
-                    break;
-                }
-            } else {
-                // chunk that were not yet generated
-                remaining_chunks.push(chunk);
+    let outbound = client.read().await.outbound.clone();
+
+    while let Some(message) = outbound.next().await {
+        let (data, sent_chunk) = match message {
+            OutboundMessage::Data(data) => (data, None),
+            OutboundMessage::Chunk(x, z) => {
+                let (sections, payload, compression) = {
+                    let world_arc = client.read().await.world.clone();
+                    let world = world_arc.read().await;
+                    let mut client = client.write().await;
+                    // queued in a world the client has left since
+                    if !Arc::ptr_eq(&client.world, &world_arc) {
+                        continue;
+                    }
+                    client.queued_chunks.remove(&(x, z));
+                    // skip chunks that are missing or the client moved away from while queued
+                    let Some(chunk) = world.get_chunk(x, z) else {
+                        continue;
+                    };
+                    if !client.demands_chunk(x, z) {
+                        continue;
+                    }
+                    let sections = client.capabilities & CAPABILITY_CHUNK_SECTIONS != 0;
+                    let payload = if sections {
+                        chunk.to_section_bytes()
+                    } else {
+                        world.chunk_rle(x, z)
+                    };
+                    // voxel changes are queued for the chunk from here on, so none can slip in between.
+                    // it only counts as loaded once it was written
+                    client.start_chunk_send(x, z);
+                    (
+                        sections,
+                        payload,
+                        Compression::from_capabilities(client.capabilities),
+                    )
+                };
+                // compression runs outside the world lock
+                let (compression, payload) = compression.compress(&payload);
+                let message = if sections {
+                    Message::ChunkSectionData {
+                        x,
+                        z,
+                        compression,
+                        payload,
+                    }
+                } else {
+                    Message::ChunkData {
+                        x,
+                        z,
+                        compression,
+                        payload,
+                    }
+                };
+                (message.encode(), Some((x, z)))
             }
-        }
-        {
-            let mut client = client.write().await;
-            client.chunk_demand = remaining_chunks;
         };
-        // send players data to client
-        
-        let world = world.read().await;
-        let player_data = world.players_to_bytes();
-        if !send_data(write_half.clone(), player_data).await {
+        if !send_data(writer.clone(), data).await {
             break;
         }
-        
-
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        if let Some((x, z)) = sent_chunk {
+            client.write().await.chunk_sent(x, z);
+        }
+        // with nothing left in the queue every voxel change so far was written. checked again
+        // under the world lock, block edits queue their VoxelChanged while holding it
+        if outbound.is_empty() {
+            let world_arc = client.read().await.world.clone();
+            let _world = world_arc.read().await;
+            if outbound.is_empty() {
+                client.write().await.synced_tick = current_tick();
+            }
+        }
     }
-    //lock write_half
+    // queue was closed or the socket failed, nothing more will be sent
+    writer.lock().await.close().await;
 }
 
-async fn send_data(write_half: Arc<Mutex<OwnedWriteHalf>>, data: Vec<u8>) -> bool {
-    let mut socket = write_half.lock().await; // Lock the mutex to get access to the write_half
-    let buffer_size: usize = 1024;
-
-    let identifier = data[4];
-
-    // Send the data in chunks
-    let data_len = data.len();
-    let mut offset = 0;
-
-    while offset < data_len {
-        // Calculate the number of bytes to send in this iteration
-        let bytes_to_send = std::cmp::min(buffer_size, data_len - offset);
-        if let Err(e) = socket
-            .write_all(&data[offset..offset + bytes_to_send])
-            .await
-        {
-            println!("Failed to send client data: {:?}", e);
+async fn send_data(writer: Arc<Mutex<FrameWriter>>, data: Vec<u8>) -> bool {
+    let identifier = data[0];
+    let data = match FrameEncoder::new(CONFIG.max_outbound_frame_size).encode(&data) {
+        Ok(frame) => frame,
+        Err(e) => {
+            println!("Failed to encode data: Identifier:{} {}", identifier, e);
             return false;
         }
+    };
+    let data_len = data.len();
 
-        offset += bytes_to_send; // Update offset
+    let mut writer = writer.lock().await; // Lock the mutex to get access to the writer
+    if let Err(e) = writer.write_frame(&data).await {
+        println!("Failed to send client data: {:?}", e);
+        return false;
     }
     //metrics
     NETWORK_BYTES_EGRESS_TOTAL.inc_by(data_len as u64);
@@ -349,59 +677,3 @@ async fn send_data(write_half: Arc<Mutex<OwnedWriteHalf>>, data: Vec<u8>) -> boo
     println!("Bytes{:?}", &data[..data.len().min(16)]);
     true
 }
-
-async fn handle_websocket(ws_stream: WebSocketStream<tokio::net::TcpStream>) {
-    let (mut ws_tx, mut ws_rx) = ws_stream.split();
-
-    // Connect to the TCP server
-    let tcp_stream = match tokio::net::TcpStream::connect("127.0.0.1:6969").await {
-        Ok(stream) => {
-            println!("Connected to TCP server!");
-            stream
-        }
-        Err(e) => {
-            eprintln!("Failed to connect to TCP server: {:?}", e);
-            return; // Exit if unable to connect
-        }
-    };
-    let (tcp_read_half, mut tcp_write_half) = tcp_stream.into_split();
-
-    let tcp_to_ws = tokio::spawn(async move {
-        let mut tcp_reader = tokio::io::BufReader::new(tcp_read_half);
-        let mut buffer = vec![0; 1024]; // Buffer for reading TCP data
-        loop {
-            match tcp_reader.read(&mut buffer).await {
-                Ok(0) => break, // Connection closed
-                Ok(n) => {
-                    // Send TCP data to WebSocket client
-                    if let Err(e) = ws_tx.send(Message::Binary(buffer[..n].to_vec())).await {
-                        eprintln!("Error sending to WebSocket: {:?}", e);
-                        break;
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error reading from TCP: {:?}", e);
-                    break;
-                }
-            }
-        }
-    });
-
-    while let Some(msg) = ws_rx.next().await {
-        match msg {
-            Ok(Message::Binary(bin)) => {
-                if let Err(e) = tcp_write_half.write_all(&bin).await {
-                    eprintln!("Error sending binary data to TCP: {:?}", e);
-                    break;
-                }
-            }
-            Err(e) => {
-                eprintln!("WebSocket error: {:?}", e);
-                break;
-            }
-            _ => {}
-        }
-    }
-
-    println!("WebSocket connection closed.");
-}
@@ -1,6 +1,7 @@
 use prometheus::{
-    gather, register_counter, register_gauge, register_histogram, register_int_counter, Counter,
-    Encoder, Gauge, Histogram, IntCounter, TextEncoder,
+    gather, register_counter, register_gauge, register_histogram, register_int_counter,
+    register_int_counter_vec, register_int_gauge_vec, Counter, Encoder, Gauge, Histogram,
+    IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
 };
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
@@ -13,10 +14,20 @@ lazy_static::lazy_static! {
     pub static ref CLIENT_COUNT:Gauge = register_gauge!("client_count"," ").unwrap();
     pub static ref CHUNK_GENERATED_COUNTER: IntCounter = register_int_counter!("chunk_generated_after_restart"," ").unwrap();
     pub static ref CHUNK_GENERATION_TIME: Histogram = register_histogram!("chunk_generation_time"," ").unwrap();
+    pub static ref CHUNKS_LOADED_TOTAL:IntCounter = register_int_counter!("chunks_loaded_total","chunks read from region files").unwrap();
+    pub static ref CHUNKS_SAVED_TOTAL:IntCounter = register_int_counter!("chunks_saved_total","modified chunks written to region files").unwrap();
     pub static ref NETWORK_BYTES_EGRESS_TOTAL:IntCounter = register_int_counter!("network_bytes_egress_total"," ").unwrap();
     pub static ref NETWORK_BYTES_INGRESS_TOTAL:IntCounter = register_int_counter!("network_bytes_ingress_total"," ").unwrap();
     pub static ref NETWORK_BYTES_EGRESS_S:Gauge = register_gauge!("network_bytes_egress_s"," ").unwrap();
     pub static ref NETWORK_BYTES_INGRESS_S:Gauge = register_gauge!("network_bytes_ingress_s"," ").unwrap();
+    pub static ref CHUNK_BYTES_RAW_TOTAL:IntCounter = register_int_counter!("chunk_bytes_raw_total","chunk payload bytes before compression").unwrap();
+    pub static ref CHUNK_BYTES_COMPRESSED_TOTAL:IntCounter = register_int_counter!("chunk_bytes_compressed_total","chunk payload bytes after compression").unwrap();
+    pub static ref CHUNK_COMPRESSION_TIME: Histogram = register_histogram!("chunk_compression_time","chunk payload compression time in ms").unwrap();
+    pub static ref CHAT_MESSAGES_TOTAL:IntCounter = register_int_counter!("chat_messages_total","chat messages delivered, incl. system messages").unwrap();
+    pub static ref REJECTED_MESSAGES_TOTAL:IntCounterVec = register_int_counter_vec!("rejected_messages_total","client messages rejected as invalid, by kind", &["kind"]).unwrap();
+    pub static ref MOVEMENT_VIOLATIONS_TOTAL:IntCounterVec = register_int_counter_vec!("movement_violations_total","rejected client positions, by kind", &["kind"]).unwrap();
+    pub static ref MOVEMENT_VIOLATION_SCORE:IntGaugeVec = register_int_gauge_vec!("movement_violation_score","movement violation score of connected players", &["client_id"]).unwrap();
+    pub static ref CLIENT_RTT: Histogram = register_histogram!("client_rtt","keepalive round trip time in ms", vec![5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0]).unwrap();
 }
 
 pub async fn start() {
@@ -60,7 +71,7 @@ async fn handle_connection(mut stream: TcpStream, request_counter: Counter) {
     let mut buffer = [0; 1024];
     // Use the async read method
     let n = match stream.read(&mut buffer).await {
-        Ok(n) if n == 0 => return, // Connection closed
+        Ok(0) => return, // Connection closed
         Ok(n) => n,
         Err(e) => {
             eprintln!("Failed to read from stream: {}", e);
@@ -0,0 +1,259 @@
+// src/movement.rs
+// Optional server authoritative movement (CONFIG.authoritative_movement). Clients negotiate
+// CAPABILITY_AUTHORITATIVE_MOVEMENT and send Input frames instead of positions, the world update task
+// simulates them with gravity and voxel collision and answers with MovementCorrection carrying the last
+// processed input seq, the client replays its newer inputs on top of the corrected state.
+// Every input is one tick of movement, so clients sample input at the server tick rate.
+// The position is the player's feet, the player is a PLAYER_WIDTH x PLAYER_HEIGHT box centered on it.
+
+use crate::chunk::{CHUNK_HEIGHT, VOXEL_AIR};
+use crate::client::Client;
+use crate::outbound::Priority;
+use crate::protocol::{Input, Message};
+use crate::world::World;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub const WALK_SPEED: f32 = 4.5; // blocks per second
+pub const GRAVITY: f32 = 28.0; // blocks per second squared
+pub const JUMP_VELOCITY: f32 = 9.0; // blocks per second, a bit over one block high
+pub const TERMINAL_VELOCITY: f32 = 60.0;
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+
+const MAX_QUEUED_INPUTS: usize = 32; // the oldest input is dropped when a client sends faster than this
+const MAX_CATCH_UP_INPUTS: usize = 3; // extra inputs a tick may simulate after ticks that had none (jitter)
+const MAX_IDLE_TICKS: u32 = 5; // ticks without input before the player is simulated standing still
+const MAX_SUBSTEP: f32 = 0.25; // longest move checked for collision at once, keeps fast falls from tunneling
+const SKIN: f32 = 0.001; // gap kept to a blocking voxel face
+
+pub struct Movement {
+    pub inputs: VecDeque<Input>,
+    pub last_input_seq: u32, // newest input queued, inputs not newer than this are dropped
+    pub processed_seq: u32,  // last input simulated, sent back in corrections
+    pub velocity_y: f32,
+    pub on_ground: bool,
+    pub idle_ticks: u32,
+    pub catch_up: usize, // extra inputs allowed, one is earned per tick without input
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Movement::new()
+    }
+}
+
+impl Movement {
+    pub fn new() -> Self {
+        Movement {
+            inputs: VecDeque::new(),
+            last_input_seq: 0,
+            processed_seq: 0,
+            velocity_y: 0.0,
+            on_ground: false,
+            idle_ticks: 0,
+            catch_up: 0,
+        }
+    }
+
+    // queues an input unless it is a duplicate or arrived after a newer one (UDP), seqs start at 1 and may wrap
+    pub fn queue_input(&mut self, input: Input) {
+        if (input.seq.wrapping_sub(self.last_input_seq) as i32) <= 0 {
+            return;
+        }
+        self.last_input_seq = input.seq;
+        self.inputs.push_back(input);
+        if self.inputs.len() > MAX_QUEUED_INPUTS {
+            self.inputs.pop_front();
+        }
+    }
+}
+
+// simulates one tick for every client using authoritative movement and sends each its correction.
+// called by the world update task with the world locked, before client positions are copied into it
+pub async fn simulate_clients(
+    world: &World,
+    clients: impl Iterator<Item = &Arc<RwLock<Client>>>,
+    tick: u64,
+    dt: f32,
+) {
+    for client_arc in clients {
+        let mut client = client_arc.write().await;
+        if !client.has_authoritative_movement() {
+            continue;
+        }
+        // one input per tick, more only to make up for ticks that had none, so the average can't exceed it
+        let inputs: Vec<Input> = {
+            let movement = &mut client.movement;
+            let queued = movement.inputs.len().min(1 + movement.catch_up);
+            let inputs: Vec<Input> = movement.inputs.drain(..queued).collect();
+            movement.catch_up = match inputs.len() {
+                0 => (movement.catch_up + 1).min(MAX_CATCH_UP_INPUTS),
+                count => movement.catch_up - (count - 1),
+            };
+            // inputs beyond what can be caught up with later are dropped, oldest first
+            while movement.inputs.len() > MAX_CATCH_UP_INPUTS {
+                movement.inputs.pop_front();
+            }
+            inputs
+        };
+        if inputs.is_empty() {
+            // a late input is waited for a few ticks, after that the player stands still and falls
+            client.movement.idle_ticks += 1;
+            if client.movement.idle_ticks <= MAX_IDLE_TICKS {
+                continue;
+            }
+            let mut position = client.position;
+            step(world, &mut position, &mut client.movement, None, dt);
+            client.position = position;
+        } else {
+            client.movement.idle_ticks = 0;
+            for input in &inputs {
+                let mut position = client.position;
+                step(world, &mut position, &mut client.movement, Some(input), dt);
+                client.position = position;
+                client.rotation.0 = input.rotation_x;
+                client.rotation.1 = input.rotation_y;
+                client.movement.processed_seq = input.seq;
+            }
+        }
+
+        let data = Message::MovementCorrection {
+            input_seq: client.movement.processed_seq,
+            tick,
+            position: client.position,
+            velocity_y: client.movement.velocity_y,
+            on_ground: client.movement.on_ground,
+        }
+        .encode();
+        // only the newest correction matters
+        if !client.send_unreliable(&data) {
+            client.outbound.push_latest(Priority::Entity, data);
+        }
+    }
+}
+
+// moves the player by one input, None is a tick without input
+pub fn step(
+    world: &World,
+    position: &mut (f32, f32, f32),
+    movement: &mut Movement,
+    input: Option<&Input>,
+    dt: f32,
+) {
+    // stuck inside a solid voxel (block placed into the player, bad spawn), pushed up instead of simulated
+    if collides(world, *position) {
+        position.1 = position.1.floor() + 1.0;
+        movement.velocity_y = 0.0;
+        return;
+    }
+
+    let (mut forward, mut strafe, jump, rotation_y) = match input {
+        Some(input) => (
+            input.forward.clamp(-1.0, 1.0),
+            input.strafe.clamp(-1.0, 1.0),
+            input.jump,
+            input.rotation_y,
+        ),
+        None => (0.0, 0.0, false, 0.0),
+    };
+    // diagonal movement is not faster
+    let length = (forward * forward + strafe * strafe).sqrt();
+    if length > 1.0 {
+        forward /= length;
+        strafe /= length;
+    }
+    // forward is -z at rotation 0, rotation y is in radians
+    let (sin, cos) = rotation_y.sin_cos();
+    let dx = (-forward * sin + strafe * cos) * WALK_SPEED * dt;
+    let dz = (-forward * cos - strafe * sin) * WALK_SPEED * dt;
+
+    if jump && movement.on_ground {
+        movement.velocity_y = JUMP_VELOCITY;
+    }
+    movement.velocity_y = (movement.velocity_y - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+
+    let mut p = [position.0, position.1, position.2];
+    move_axis(world, &mut p, 0, dx);
+    move_axis(world, &mut p, 2, dz);
+    let falling = movement.velocity_y < 0.0;
+    let blocked = move_axis(world, &mut p, 1, movement.velocity_y * dt);
+    movement.on_ground = blocked && falling;
+    if blocked {
+        movement.velocity_y = 0.0;
+    }
+    *position = (p[0], p[1], p[2]);
+}
+
+// moves along one axis until a voxel blocks, ending flush against it. returns true if blocked
+fn move_axis(world: &World, position: &mut [f32; 3], axis: usize, delta: f32) -> bool {
+    if delta == 0.0 {
+        return false;
+    }
+    let steps = (delta.abs() / MAX_SUBSTEP).ceil();
+    let step = delta / steps;
+    for _ in 0..steps as u32 {
+        let mut next = *position;
+        next[axis] += step;
+        if !collides(world, (next[0], next[1], next[2])) {
+            *position = next;
+            continue;
+        }
+        let (min, max) = extent(&next, axis);
+        if step > 0.0 {
+            next[axis] -= max - max.floor() + SKIN;
+        } else {
+            next[axis] += min.ceil() - min + SKIN;
+        }
+        let closer = (next[axis] - position[axis]) * step > 0.0;
+        if closer && !collides(world, (next[0], next[1], next[2])) {
+            *position = next;
+        }
+        return true;
+    }
+    false
+}
+
+// min and max of the player's box along an axis
+fn extent(position: &[f32; 3], axis: usize) -> (f32, f32) {
+    match axis {
+        1 => (position[1], position[1] + PLAYER_HEIGHT),
+        _ => (
+            position[axis] - PLAYER_WIDTH / 2.0,
+            position[axis] + PLAYER_WIDTH / 2.0,
+        ),
+    }
+}
+
+// true if the player's box at the position overlaps a solid voxel
+pub fn collides(world: &World, position: (f32, f32, f32)) -> bool {
+    let p = [position.0, position.1, position.2];
+    let (min_x, max_x) = extent(&p, 0);
+    let (min_y, max_y) = extent(&p, 1);
+    let (min_z, max_z) = extent(&p, 2);
+    for x in min_x.floor() as i32..max_x.ceil() as i32 {
+        for y in min_y.floor() as i32..max_y.ceil() as i32 {
+            for z in min_z.floor() as i32..max_z.ceil() as i32 {
+                if is_solid(world, x, y, z) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// below the world and unloaded chunks block movement, above the world is open
+pub fn is_solid(world: &World, x: i32, y: i32, z: i32) -> bool {
+    if y < 0 {
+        return true;
+    }
+    if y >= CHUNK_HEIGHT as i32 {
+        return false;
+    }
+    match world.get_voxel_at(x, y, z) {
+        Some(id) => id != VOXEL_AIR,
+        None => true,
+    }
+}
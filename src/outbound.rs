@@ -0,0 +1,130 @@
+// src/outbound.rs
+// Per-client outbound queue. Any subsystem can enqueue, a single writer task drains it
+// highest priority first, so bulk chunk data never delays control or movement messages.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+#[derive(Clone, Copy)]
+pub enum Priority {
+    Control = 0,    // handshake, initialize, keepalive
+    Entity = 1,     // player/entity updates
+    VoxelDelta = 2, // single voxel changes
+    Chat = 3,       // chat and system messages
+    Bulk = 4,       // chunk data
+}
+
+const PRIORITY_COUNT: usize = 5;
+
+pub enum OutboundMessage {
+    Data(Vec<u8>),   // already serialized message (identifier + payload)
+    Chunk(i32, i32), // chunk serialized by the writer right before sending, so it is never stale
+}
+
+pub struct OutboundQueue {
+    queues: Mutex<[VecDeque<OutboundMessage>; PRIORITY_COUNT]>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+impl Default for OutboundQueue {
+    fn default() -> Self {
+        OutboundQueue::new()
+    }
+}
+
+impl OutboundQueue {
+    pub fn new() -> Self {
+        OutboundQueue {
+            queues: Mutex::new(Default::default()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    // messages pushed after the queue was closed are dropped
+    pub fn push(&self, priority: Priority, message: OutboundMessage) {
+        {
+            let mut queues = self.queues.lock().unwrap();
+            if self.is_closed() {
+                return;
+            }
+            queues[priority as usize].push_back(message);
+        }
+        self.notify.notify_one();
+    }
+
+    pub fn push_data(&self, priority: Priority, data: Vec<u8>) {
+        self.push(priority, OutboundMessage::Data(data));
+    }
+
+    // queues data and drops older queued messages with the same identifier,
+    // for snapshots where only the latest one matters
+    pub fn push_latest(&self, priority: Priority, data: Vec<u8>) {
+        let identifier = data[0];
+        {
+            let mut queues = self.queues.lock().unwrap();
+            if self.is_closed() {
+                return;
+            }
+            let queue = &mut queues[priority as usize];
+            queue.retain(|message| match message {
+                OutboundMessage::Data(queued) => queued[0] != identifier,
+                OutboundMessage::Chunk(..) => true,
+            });
+            queue.push_back(OutboundMessage::Data(data));
+        }
+        self.notify.notify_one();
+    }
+
+    // drops every queued chunk, the caller clears Client.queued_chunks
+    pub fn clear_chunks(&self) {
+        let mut queues = self.queues.lock().unwrap();
+        for queue in queues.iter_mut() {
+            queue.retain(|message| !matches!(message, OutboundMessage::Chunk(..)));
+        }
+    }
+
+    pub fn pop(&self) -> Option<OutboundMessage> {
+        let mut queues = self.queues.lock().unwrap();
+        queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    // waits for the next message, None once the queue is closed and empty
+    pub async fn next(&self) -> Option<OutboundMessage> {
+        loop {
+            if let Some(message) = self.pop() {
+                return Some(message);
+            }
+            if self.is_closed() {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.lock().unwrap().iter().all(|queue| queue.is_empty())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    // drops queued messages, sends final_data (if any) as the last message and stops the writer task
+    pub fn close_with(&self, final_data: Option<Vec<u8>>) {
+        {
+            let mut queues = self.queues.lock().unwrap();
+            for queue in queues.iter_mut() {
+                queue.clear();
+            }
+            if let Some(data) = final_data {
+                queues[Priority::Control as usize].push_back(OutboundMessage::Data(data));
+            }
+            self.closed.store(true, Ordering::Release);
+        }
+        self.notify.notify_one();
+    }
+}
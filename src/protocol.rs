@@ -0,0 +1,1017 @@
+// src/protocol.rs
+// Every message of the protocol with symmetric encode/decode, so the byte layouts live in one place.
+// encode returns identifier + payload, the length header is added by the frame encoder.
+// All numbers are little-endian, strings are [length (u16)][utf8].
+//
+// InitializeData    [client id (u32)][position (3 x f32)][state (u32)][session token (u64)]
+// ClientData        [client id (u32), unused][position (3 x f32)][rotation y, x (2 x f32)][state (u32)]
+//                   [chunk demand: x (i32), z (i32), distance (i32)]...
+// ChunkData         [chunk x (i32)][chunk z (i32)][compression (u8)][payload, run length encoded voxel ids]
+// Keepalive         [sequence (u32)]
+// PlayerData        [tick (u64)][server time ms (u64)][player count (u32)][player]...
+// BlockPlace        [x (i32)][y (i32)][z (i32)][voxel id (u8)]
+// BlockBreak        [x (i32)][y (i32)][z (i32)]
+// VoxelChanged      [x (i32)][y (i32)][z (i32)][voxel id (u8)]
+// Hello             [protocol version (u16)][capability flags (u32)][session token (u64), optional]
+// HelloResponse     [accepted (u8)][protocol version (u16)][negotiated capabilities (u32)][reason (string)]
+// PlayerEnterView   [player]
+// PlayerLeaveView   [player id (u32)]
+// ChunkSectionData  [chunk x (i32)][chunk z (i32)][compression (u8)][payload, see Chunk::to_section_bytes]
+// ChunkUnload       [chunk x (i32)][chunk z (i32)]
+// Disconnect        [reason (u8)][message (string)]
+// UdpToken          [udp token (u64)][udp port (u16)]
+// Chat              [channel (u8)][target id to server, sender id to client (u32)][text (string)]
+// TimeSync          [client time (u64)][server time ms (u64)][tick (u64)], the client sends zeros for the server
+//                   fields and gets its client time echoed back with the server's clock when it was received
+// Input             [input seq (u32)][forward (f32)][strafe (f32)][jump (u8)][rotation y, x (2 x f32)]
+// MovementCorrection [last processed input seq (u32)][tick (u64)][position (3 x f32)][vertical velocity (f32)]
+//                   [on ground (u8)], input seq 0 rubber-bands a client whose reported position was rejected
+// EntitySpawn       [entity]
+// EntityUpdate      [tick (u64)][entity count (u32)][entity id (u32)][position (3 x f32)][velocity (3 x f32)]...
+// EntityDespawn     [entity id (u32)]
+// ChangeWorld       [world name (string)][position (3 x f32)], the client asks to move with its position ignored,
+//                   the server answers with the spawn position in the new world
+//
+// player: [id (u32)][position (3 x f32)][rotation (3 x f32)][state (u32)] (32 bytes)
+// entity: [id (u32)][kind (u8)][position (3 x f32)][velocity (3 x f32)][data (bytes)]
+// bytes: [length (u16)][data]
+
+use crate::chat::ChatChannel;
+use crate::compression::Compression;
+use crate::data::DataIdentifier;
+use crate::entity::{Entity, EntityKind, EntityState};
+use crate::session::DisconnectReason;
+use crate::world::Player;
+use std::fmt;
+
+// more chunk demand entries than this in one ClientData are rejected, far above any sane view distance
+pub const MAX_CHUNK_DEMAND: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientData {
+    pub client_id: u32,
+    pub position: (f32, f32, f32),
+    pub rotation_y: f32,
+    pub rotation_x: f32,
+    pub state: u32,
+    pub chunk_demand: Vec<(i32, i32, i32)>, // chunk x, z, distance
+}
+
+// one tick of player input for authoritative movement, forward and strafe are -1..1 relative to rotation y
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Input {
+    pub seq: u32,
+    pub forward: f32,
+    pub strafe: f32,
+    pub jump: bool,
+    pub rotation_y: f32,
+    pub rotation_x: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub protocol_version: u16,
+    pub capabilities: u32,
+    pub session_token: Option<u64>, // token from a previous InitializeData
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    InitializeData {
+        client_id: u32,
+        position: (f32, f32, f32),
+        state: u32,
+        session_token: u64,
+    },
+    ClientData(ClientData),
+    ChunkData {
+        x: i32,
+        z: i32,
+        compression: Compression,
+        payload: Vec<u8>,
+    },
+    Keepalive {
+        seq: u32,
+    },
+    PlayerData {
+        tick: u64,
+        server_time: u64,
+        players: Vec<Player>,
+    },
+    BlockPlace {
+        x: i32,
+        y: i32,
+        z: i32,
+        id: u8,
+    },
+    BlockBreak {
+        x: i32,
+        y: i32,
+        z: i32,
+    },
+    VoxelChanged {
+        x: i32,
+        y: i32,
+        z: i32,
+        id: u8,
+    },
+    Hello(Hello),
+    HelloResponse {
+        accepted: bool,
+        protocol_version: u16,
+        capabilities: u32,
+        reason: String,
+    },
+    PlayerEnterView {
+        player: Player,
+    },
+    PlayerLeaveView {
+        player_id: u32,
+    },
+    ChunkSectionData {
+        x: i32,
+        z: i32,
+        compression: Compression,
+        payload: Vec<u8>,
+    },
+    ChunkUnload {
+        x: i32,
+        z: i32,
+    },
+    Disconnect {
+        reason: DisconnectReason,
+        message: String,
+    },
+    UdpToken {
+        token: u64,
+        port: u16,
+    },
+    Chat {
+        channel: ChatChannel,
+        peer_id: u32, // target id from a client, sender id (0 for system) from the server
+        text: String,
+    },
+    TimeSync {
+        client_time: u64, // opaque to the server, echoed back
+        server_time: u64,
+        tick: u64,
+    },
+    Input(Input),
+    MovementCorrection {
+        input_seq: u32,
+        tick: u64,
+        position: (f32, f32, f32),
+        velocity_y: f32,
+        on_ground: bool,
+    },
+    EntitySpawn {
+        entity: Entity,
+    },
+    EntityUpdate {
+        tick: u64,
+        entities: Vec<EntityState>,
+    },
+    EntityDespawn {
+        entity_id: u32,
+    },
+    ChangeWorld {
+        name: String,
+        position: (f32, f32, f32),
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Empty,
+    UnknownIdentifier(u8),
+    Truncated(usize),     // offset where more bytes were expected
+    TrailingBytes(usize), // bytes left after the message
+    InvalidValue(&'static str),
+    NonFinite(usize),             // offset of a NaN or infinite float
+    TooMany(&'static str, usize), // what, count
+}
+
+impl DecodeError {
+    // short name used as metric label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DecodeError::Empty => "empty",
+            DecodeError::UnknownIdentifier(_) => "unknown_identifier",
+            DecodeError::Truncated(_) => "truncated",
+            DecodeError::TrailingBytes(_) => "trailing_bytes",
+            DecodeError::InvalidValue(_) => "invalid_value",
+            DecodeError::NonFinite(_) => "non_finite",
+            DecodeError::TooMany(..) => "too_many",
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "empty message"),
+            DecodeError::UnknownIdentifier(identifier) => {
+                write!(f, "unknown identifier {}", identifier)
+            }
+            DecodeError::Truncated(offset) => write!(f, "message truncated at byte {}", offset),
+            DecodeError::TrailingBytes(count) => write!(f, "{} unexpected trailing bytes", count),
+            DecodeError::InvalidValue(field) => write!(f, "invalid {}", field),
+            DecodeError::NonFinite(offset) => write!(f, "NaN or infinite float at byte {}", offset),
+            DecodeError::TooMany(what, count) => write!(f, "too many {} ({})", what, count),
+        }
+    }
+}
+
+impl Message {
+    pub fn identifier(&self) -> DataIdentifier {
+        match self {
+            Message::InitializeData { .. } => DataIdentifier::InitializeData,
+            Message::ClientData(_) => DataIdentifier::ClientData,
+            Message::ChunkData { .. } => DataIdentifier::ChunkData,
+            Message::Keepalive { .. } => DataIdentifier::Keepalive,
+            Message::PlayerData { .. } => DataIdentifier::PlayerData,
+            Message::BlockPlace { .. } => DataIdentifier::BlockPlace,
+            Message::BlockBreak { .. } => DataIdentifier::BlockBreak,
+            Message::VoxelChanged { .. } => DataIdentifier::VoxelChanged,
+            Message::Hello(_) => DataIdentifier::Hello,
+            Message::HelloResponse { .. } => DataIdentifier::HelloResponse,
+            Message::PlayerEnterView { .. } => DataIdentifier::PlayerEnterView,
+            Message::PlayerLeaveView { .. } => DataIdentifier::PlayerLeaveView,
+            Message::ChunkSectionData { .. } => DataIdentifier::ChunkSectionData,
+            Message::ChunkUnload { .. } => DataIdentifier::ChunkUnload,
+            Message::Disconnect { .. } => DataIdentifier::Disconnect,
+            Message::UdpToken { .. } => DataIdentifier::UdpToken,
+            Message::Chat { .. } => DataIdentifier::Chat,
+            Message::TimeSync { .. } => DataIdentifier::TimeSync,
+            Message::Input(_) => DataIdentifier::Input,
+            Message::MovementCorrection { .. } => DataIdentifier::MovementCorrection,
+            Message::EntitySpawn { .. } => DataIdentifier::EntitySpawn,
+            Message::EntityUpdate { .. } => DataIdentifier::EntityUpdate,
+            Message::EntityDespawn { .. } => DataIdentifier::EntityDespawn,
+            Message::ChangeWorld { .. } => DataIdentifier::ChangeWorld,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.identifier() as u8];
+        match self {
+            Message::InitializeData {
+                client_id,
+                position,
+                state,
+                session_token,
+            } => {
+                data.extend(client_id.to_le_bytes());
+                put_vec3(&mut data, *position);
+                data.extend(state.to_le_bytes());
+                data.extend(session_token.to_le_bytes());
+            }
+            Message::ClientData(client_data) => {
+                data.extend(client_data.client_id.to_le_bytes());
+                put_vec3(&mut data, client_data.position);
+                data.extend(client_data.rotation_y.to_le_bytes());
+                data.extend(client_data.rotation_x.to_le_bytes());
+                data.extend(client_data.state.to_le_bytes());
+                for (x, z, distance) in &client_data.chunk_demand {
+                    data.extend(x.to_le_bytes());
+                    data.extend(z.to_le_bytes());
+                    data.extend(distance.to_le_bytes());
+                }
+            }
+            Message::ChunkData {
+                x,
+                z,
+                compression,
+                payload,
+            }
+            | Message::ChunkSectionData {
+                x,
+                z,
+                compression,
+                payload,
+            } => {
+                data.reserve(payload.len() + 9);
+                data.extend(x.to_le_bytes());
+                data.extend(z.to_le_bytes());
+                data.push(*compression as u8);
+                data.extend(payload);
+            }
+            Message::Keepalive { seq } => data.extend(seq.to_le_bytes()),
+            Message::PlayerData {
+                tick,
+                server_time,
+                players,
+            } => {
+                data.extend(tick.to_le_bytes());
+                data.extend(server_time.to_le_bytes());
+                data.extend((players.len() as u32).to_le_bytes());
+                for player in players {
+                    put_player(&mut data, player);
+                }
+            }
+            Message::BlockPlace { x, y, z, id } | Message::VoxelChanged { x, y, z, id } => {
+                data.extend(x.to_le_bytes());
+                data.extend(y.to_le_bytes());
+                data.extend(z.to_le_bytes());
+                data.push(*id);
+            }
+            Message::BlockBreak { x, y, z } => {
+                data.extend(x.to_le_bytes());
+                data.extend(y.to_le_bytes());
+                data.extend(z.to_le_bytes());
+            }
+            Message::Hello(hello) => {
+                data.extend(hello.protocol_version.to_le_bytes());
+                data.extend(hello.capabilities.to_le_bytes());
+                if let Some(session_token) = hello.session_token {
+                    data.extend(session_token.to_le_bytes());
+                }
+            }
+            Message::HelloResponse {
+                accepted,
+                protocol_version,
+                capabilities,
+                reason,
+            } => {
+                data.push(*accepted as u8);
+                data.extend(protocol_version.to_le_bytes());
+                data.extend(capabilities.to_le_bytes());
+                put_string(&mut data, reason);
+            }
+            Message::PlayerEnterView { player } => put_player(&mut data, player),
+            Message::PlayerLeaveView { player_id } => data.extend(player_id.to_le_bytes()),
+            Message::ChunkUnload { x, z } => {
+                data.extend(x.to_le_bytes());
+                data.extend(z.to_le_bytes());
+            }
+            Message::Disconnect { reason, message } => {
+                data.push(*reason as u8);
+                put_string(&mut data, message);
+            }
+            Message::UdpToken { token, port } => {
+                data.extend(token.to_le_bytes());
+                data.extend(port.to_le_bytes());
+            }
+            Message::Chat {
+                channel,
+                peer_id,
+                text,
+            } => {
+                data.push(*channel as u8);
+                data.extend(peer_id.to_le_bytes());
+                put_string(&mut data, text);
+            }
+            Message::TimeSync {
+                client_time,
+                server_time,
+                tick,
+            } => {
+                data.extend(client_time.to_le_bytes());
+                data.extend(server_time.to_le_bytes());
+                data.extend(tick.to_le_bytes());
+            }
+            Message::Input(input) => {
+                data.extend(input.seq.to_le_bytes());
+                data.extend(input.forward.to_le_bytes());
+                data.extend(input.strafe.to_le_bytes());
+                data.push(input.jump as u8);
+                data.extend(input.rotation_y.to_le_bytes());
+                data.extend(input.rotation_x.to_le_bytes());
+            }
+            Message::MovementCorrection {
+                input_seq,
+                tick,
+                position,
+                velocity_y,
+                on_ground,
+            } => {
+                data.extend(input_seq.to_le_bytes());
+                data.extend(tick.to_le_bytes());
+                put_vec3(&mut data, *position);
+                data.extend(velocity_y.to_le_bytes());
+                data.push(*on_ground as u8);
+            }
+            Message::EntitySpawn { entity } => {
+                data.extend(entity.id.to_le_bytes());
+                data.push(entity.kind as u8);
+                put_vec3(&mut data, entity.position);
+                put_vec3(&mut data, entity.velocity);
+                put_bytes(&mut data, &entity.data);
+            }
+            Message::EntityUpdate { tick, entities } => {
+                data.extend(tick.to_le_bytes());
+                data.extend((entities.len() as u32).to_le_bytes());
+                for (id, position, velocity) in entities {
+                    data.extend(id.to_le_bytes());
+                    put_vec3(&mut data, *position);
+                    put_vec3(&mut data, *velocity);
+                }
+            }
+            Message::EntityDespawn { entity_id } => data.extend(entity_id.to_le_bytes()),
+            Message::ChangeWorld { name, position } => {
+                put_string(&mut data, name);
+                put_vec3(&mut data, *position);
+            }
+        }
+        data
+    }
+
+    // decodes a whole message, bytes left over are an error
+    pub fn decode(data: &[u8]) -> Result<Message, DecodeError> {
+        let mut reader = Reader::new(data);
+        let identifier = reader.u8().map_err(|_| DecodeError::Empty)?;
+        let identifier = DataIdentifier::from_u8(identifier)
+            .ok_or(DecodeError::UnknownIdentifier(identifier))?;
+        let message = match identifier {
+            DataIdentifier::InitializeData => Message::InitializeData {
+                client_id: reader.u32()?,
+                position: reader.vec3()?,
+                state: reader.u32()?,
+                session_token: reader.u64()?,
+            },
+            DataIdentifier::ClientData => {
+                let client_id = reader.u32()?;
+                let position = reader.vec3()?;
+                let rotation_y = reader.f32()?;
+                let rotation_x = reader.f32()?;
+                let state = reader.u32()?;
+                let count = reader.remaining() / 12;
+                if count > MAX_CHUNK_DEMAND {
+                    return Err(DecodeError::TooMany("chunk demand entries", count));
+                }
+                let mut chunk_demand = Vec::with_capacity(count);
+                while reader.remaining() > 0 {
+                    chunk_demand.push((reader.i32()?, reader.i32()?, reader.i32()?));
+                }
+                Message::ClientData(ClientData {
+                    client_id,
+                    position,
+                    rotation_y,
+                    rotation_x,
+                    state,
+                    chunk_demand,
+                })
+            }
+            DataIdentifier::ChunkData => Message::ChunkData {
+                x: reader.i32()?,
+                z: reader.i32()?,
+                compression: reader.compression()?,
+                payload: reader.rest().to_vec(),
+            },
+            DataIdentifier::Keepalive => Message::Keepalive { seq: reader.u32()? },
+            DataIdentifier::PlayerData => {
+                let tick = reader.u64()?;
+                let server_time = reader.u64()?;
+                let count = reader.u32()? as usize;
+                // each player is 32 bytes, a count the data can't hold is rejected before allocating
+                if count > reader.remaining() / 32 {
+                    return Err(DecodeError::InvalidValue("player count"));
+                }
+                let mut players = Vec::with_capacity(count);
+                for _ in 0..count {
+                    players.push(reader.player()?);
+                }
+                Message::PlayerData {
+                    tick,
+                    server_time,
+                    players,
+                }
+            }
+            DataIdentifier::BlockPlace => Message::BlockPlace {
+                x: reader.i32()?,
+                y: reader.i32()?,
+                z: reader.i32()?,
+                id: reader.u8()?,
+            },
+            DataIdentifier::BlockBreak => Message::BlockBreak {
+                x: reader.i32()?,
+                y: reader.i32()?,
+                z: reader.i32()?,
+            },
+            DataIdentifier::VoxelChanged => Message::VoxelChanged {
+                x: reader.i32()?,
+                y: reader.i32()?,
+                z: reader.i32()?,
+                id: reader.u8()?,
+            },
+            DataIdentifier::Hello => Message::Hello(Hello {
+                protocol_version: reader.u16()?,
+                capabilities: reader.u32()?,
+                session_token: match reader.remaining() {
+                    0 => None,
+                    _ => Some(reader.u64()?),
+                },
+            }),
+            DataIdentifier::HelloResponse => Message::HelloResponse {
+                accepted: reader.bool("accepted flag")?,
+                protocol_version: reader.u16()?,
+                capabilities: reader.u32()?,
+                reason: reader.string()?,
+            },
+            DataIdentifier::PlayerEnterView => Message::PlayerEnterView {
+                player: reader.player()?,
+            },
+            DataIdentifier::PlayerLeaveView => Message::PlayerLeaveView {
+                player_id: reader.u32()?,
+            },
+            DataIdentifier::ChunkSectionData => Message::ChunkSectionData {
+                x: reader.i32()?,
+                z: reader.i32()?,
+                compression: reader.compression()?,
+                payload: reader.rest().to_vec(),
+            },
+            DataIdentifier::ChunkUnload => Message::ChunkUnload {
+                x: reader.i32()?,
+                z: reader.i32()?,
+            },
+            DataIdentifier::Disconnect => Message::Disconnect {
+                reason: DisconnectReason::from_u8(reader.u8()?)
+                    .ok_or(DecodeError::InvalidValue("disconnect reason"))?,
+                message: reader.string()?,
+            },
+            DataIdentifier::UdpToken => Message::UdpToken {
+                token: reader.u64()?,
+                port: reader.u16()?,
+            },
+            DataIdentifier::Chat => Message::Chat {
+                channel: ChatChannel::from_u8(reader.u8()?)
+                    .ok_or(DecodeError::InvalidValue("chat channel"))?,
+                peer_id: reader.u32()?,
+                text: reader.string()?,
+            },
+            DataIdentifier::TimeSync => Message::TimeSync {
+                client_time: reader.u64()?,
+                server_time: reader.u64()?,
+                tick: reader.u64()?,
+            },
+            DataIdentifier::Input => Message::Input(Input {
+                seq: reader.u32()?,
+                forward: reader.f32()?,
+                strafe: reader.f32()?,
+                jump: reader.bool("jump flag")?,
+                rotation_y: reader.f32()?,
+                rotation_x: reader.f32()?,
+            }),
+            DataIdentifier::MovementCorrection => Message::MovementCorrection {
+                input_seq: reader.u32()?,
+                tick: reader.u64()?,
+                position: reader.vec3()?,
+                velocity_y: reader.f32()?,
+                on_ground: reader.bool("on ground flag")?,
+            },
+            DataIdentifier::EntitySpawn => Message::EntitySpawn {
+                entity: Entity {
+                    id: reader.u32()?,
+                    kind: EntityKind::from_u8(reader.u8()?)
+                        .ok_or(DecodeError::InvalidValue("entity kind"))?,
+                    position: reader.vec3()?,
+                    velocity: reader.vec3()?,
+                    data: reader.bytes_u16()?.to_vec(),
+                    expires_at: None,
+                },
+            },
+            DataIdentifier::EntityUpdate => {
+                let tick = reader.u64()?;
+                let count = reader.u32()? as usize;
+                // each entity is 28 bytes, a count the data can't hold is rejected before allocating
+                if count > reader.remaining() / 28 {
+                    return Err(DecodeError::InvalidValue("entity count"));
+                }
+                let mut entities = Vec::with_capacity(count);
+                for _ in 0..count {
+                    entities.push((reader.u32()?, reader.vec3()?, reader.vec3()?));
+                }
+                Message::EntityUpdate { tick, entities }
+            }
+            DataIdentifier::EntityDespawn => Message::EntityDespawn {
+                entity_id: reader.u32()?,
+            },
+            DataIdentifier::ChangeWorld => Message::ChangeWorld {
+                name: reader.string()?,
+                position: reader.vec3()?,
+            },
+        };
+        match reader.remaining() {
+            0 => Ok(message),
+            count => Err(DecodeError::TrailingBytes(count)),
+        }
+    }
+}
+
+fn put_vec3(data: &mut Vec<u8>, value: (f32, f32, f32)) {
+    data.extend(value.0.to_le_bytes());
+    data.extend(value.1.to_le_bytes());
+    data.extend(value.2.to_le_bytes());
+}
+
+fn put_player(data: &mut Vec<u8>, player: &Player) {
+    data.extend(player.id.to_le_bytes());
+    put_vec3(data, player.position);
+    put_vec3(data, player.rotation);
+    data.extend(player.state.to_le_bytes());
+}
+
+// byte data longer than u16::MAX is cut
+fn put_bytes(data: &mut Vec<u8>, value: &[u8]) {
+    let length = value.len().min(u16::MAX as usize);
+    data.extend((length as u16).to_le_bytes());
+    data.extend(&value[..length]);
+}
+
+// strings longer than u16::MAX bytes are cut at a character boundary
+fn put_string(data: &mut Vec<u8>, value: &str) {
+    let mut length = value.len().min(u16::MAX as usize);
+    while !value.is_char_boundary(length) {
+        length -= 1;
+    }
+    data.extend((length as u16).to_le_bytes());
+    data.extend(&value.as_bytes()[..length]);
+}
+
+// reads values from a message front to back, running out of bytes is an error instead of a panic
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, offset: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + N)
+            .ok_or(DecodeError::Truncated(self.offset))?;
+        self.offset += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.offset..];
+        self.offset = self.data.len();
+        rest
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn bool(&mut self, field: &'static str) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue(field)),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_le_bytes(self.bytes()?))
+    }
+
+    // NaN and infinity are never valid in any message
+    fn f32(&mut self) -> Result<f32, DecodeError> {
+        let offset = self.offset;
+        let value = f32::from_le_bytes(self.bytes()?);
+        if !value.is_finite() {
+            return Err(DecodeError::NonFinite(offset));
+        }
+        Ok(value)
+    }
+
+    fn vec3(&mut self) -> Result<(f32, f32, f32), DecodeError> {
+        Ok((self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    // [length (u16)][data]
+    fn bytes_u16(&mut self) -> Result<&'a [u8], DecodeError> {
+        let length = self.u16()? as usize;
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + length)
+            .ok_or(DecodeError::Truncated(self.offset))?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let bytes = self.bytes_u16()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidValue("utf8 string"))
+    }
+
+    fn compression(&mut self) -> Result<Compression, DecodeError> {
+        Compression::from_u8(self.u8()?).ok_or(DecodeError::InvalidValue("compression"))
+    }
+
+    fn player(&mut self) -> Result<Player, DecodeError> {
+        Ok(Player::new(
+            self.u32()?,
+            self.vec3()?,
+            self.vec3()?,
+            self.u32()?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(id: u32) -> Player {
+        Player::new(id, (1.5, -2.0, 300.25), (0.0, 1.25, -3.5), 7)
+    }
+
+    fn all_messages() -> Vec<Message> {
+        vec![
+            Message::InitializeData {
+                client_id: 3,
+                position: (31.0, 179.0, -31.5),
+                state: 1,
+                session_token: u64::MAX - 5,
+            },
+            Message::ClientData(ClientData {
+                client_id: 0,
+                position: (5.5, 90.0, -5.5),
+                rotation_y: 1.0,
+                rotation_x: -0.5,
+                state: 2,
+                chunk_demand: vec![(0, 0, 0), (-1, 2, 3)],
+            }),
+            Message::ClientData(ClientData {
+                client_id: 0,
+                position: (0.0, 0.0, 0.0),
+                rotation_y: 0.0,
+                rotation_x: 0.0,
+                state: 0,
+                chunk_demand: vec![],
+            }),
+            Message::ChunkData {
+                x: -4,
+                z: 9,
+                compression: Compression::Deflate,
+                payload: vec![255, 1, 17, 0],
+            },
+            Message::Keepalive { seq: 42 },
+            Message::PlayerData {
+                tick: 0,
+                server_time: 0,
+                players: vec![],
+            },
+            Message::PlayerData {
+                tick: 1234,
+                server_time: 123456,
+                players: vec![player(1), player(2)],
+            },
+            Message::BlockPlace {
+                x: -1,
+                y: 100,
+                z: 64,
+                id: 1,
+            },
+            Message::BlockBreak { x: 3, y: 0, z: -64 },
+            Message::VoxelChanged {
+                x: 1,
+                y: 2,
+                z: 3,
+                id: 0,
+            },
+            Message::Hello(Hello {
+                protocol_version: 3,
+                capabilities: 0b11111,
+                session_token: None,
+            }),
+            Message::Hello(Hello {
+                protocol_version: 3,
+                capabilities: 1,
+                session_token: Some(123456789),
+            }),
+            Message::HelloResponse {
+                accepted: false,
+                protocol_version: 3,
+                capabilities: 0,
+                reason: "unsupported protocol version".to_string(),
+            },
+            Message::PlayerEnterView { player: player(9) },
+            Message::PlayerLeaveView { player_id: 9 },
+            Message::ChunkSectionData {
+                x: 0,
+                z: -1,
+                compression: Compression::Zstd,
+                payload: vec![16, 0, 1, 1],
+            },
+            Message::ChunkUnload { x: 7, z: -7 },
+            Message::Disconnect {
+                reason: DisconnectReason::Timeout,
+                message: "keepalive timeout".to_string(),
+            },
+            Message::UdpToken {
+                token: 99,
+                port: 6971,
+            },
+            Message::Chat {
+                channel: ChatChannel::Whisper,
+                peer_id: 2,
+                text: "hej, grüße 👋".to_string(),
+            },
+            Message::TimeSync {
+                client_time: u64::MAX,
+                server_time: 5000,
+                tick: 50,
+            },
+            Message::Input(Input {
+                seq: 17,
+                forward: 1.0,
+                strafe: -0.5,
+                jump: true,
+                rotation_y: 3.1,
+                rotation_x: -1.2,
+            }),
+            Message::MovementCorrection {
+                input_seq: 17,
+                tick: 900,
+                position: (0.5, 64.0, -12.25),
+                velocity_y: -9.5,
+                on_ground: false,
+            },
+            Message::EntitySpawn {
+                entity: Entity {
+                    id: 3,
+                    kind: EntityKind::Item,
+                    position: (10.5, 120.5, -3.5),
+                    velocity: (0.0, 3.0, 0.0),
+                    data: vec![1],
+                    expires_at: None,
+                },
+            },
+            Message::EntityUpdate {
+                tick: 0,
+                entities: vec![],
+            },
+            Message::EntityUpdate {
+                tick: 77,
+                entities: vec![
+                    (3, (10.5, 119.0, -3.5), (0.0, -2.8, 0.0)),
+                    (4, (0.0, 0.0, 0.0), (0.0, 0.0, 0.0)),
+                ],
+            },
+            Message::EntityDespawn { entity_id: 3 },
+            Message::ChangeWorld {
+                name: "nether".to_string(),
+                position: (31.5, 120.0, 31.5),
+            },
+        ]
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for message in all_messages() {
+            let data = message.encode();
+            assert_eq!(data[0], message.identifier() as u8);
+            assert_eq!(Message::decode(&data), Ok(message));
+        }
+    }
+
+    #[test]
+    fn every_identifier_is_covered() {
+        let covered: Vec<u8> = all_messages()
+            .iter()
+            .map(|message| message.identifier() as u8)
+            .collect();
+        for identifier in 0..=u8::MAX {
+            if DataIdentifier::from_u8(identifier).is_some() {
+                assert!(covered.contains(&identifier), "identifier {}", identifier);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        for message in all_messages() {
+            let data = message.encode();
+            for length in 1..data.len() {
+                let decoded = Message::decode(&data[..length]);
+                // messages ending in an optional or open ended part can be complete when cut short
+                if let Ok(decoded) = decoded {
+                    assert_ne!(decoded, message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layouts_match_the_documented_offsets() {
+        let data = Message::InitializeData {
+            client_id: 1,
+            position: (2.0, 3.0, 4.0),
+            state: 5,
+            session_token: 6,
+        }
+        .encode();
+        assert_eq!(data.len(), 29);
+        assert_eq!(data[1..5], 1u32.to_le_bytes());
+        assert_eq!(data[17..21], 5u32.to_le_bytes());
+        assert_eq!(data[21..29], 6u64.to_le_bytes());
+
+        let data = Message::ClientData(ClientData {
+            client_id: 0,
+            position: (0.0, 0.0, 0.0),
+            rotation_y: 0.0,
+            rotation_x: 0.0,
+            state: 8,
+            chunk_demand: vec![(1, 2, 3)],
+        })
+        .encode();
+        assert_eq!(data.len(), 41);
+        assert_eq!(data[25..29], 8u32.to_le_bytes());
+        assert_eq!(data[29..33], 1i32.to_le_bytes());
+
+        let data = Message::PlayerEnterView { player: player(1) }.encode();
+        assert_eq!(data.len(), 33);
+
+        let data = Message::PlayerData {
+            tick: 7,
+            server_time: 700,
+            players: vec![player(1)],
+        }
+        .encode();
+        assert_eq!(data.len(), 53);
+        assert_eq!(data[1..9], 7u64.to_le_bytes());
+        assert_eq!(data[9..17], 700u64.to_le_bytes());
+        assert_eq!(data[17..21], 1u32.to_le_bytes());
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        assert_eq!(Message::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            Message::decode(&[200]),
+            Err(DecodeError::UnknownIdentifier(200))
+        );
+        let mut data = Message::Keepalive { seq: 1 }.encode();
+        data.push(0);
+        assert_eq!(Message::decode(&data), Err(DecodeError::TrailingBytes(1)));
+        // partial chunk demand entry
+        let mut data = Message::ClientData(ClientData {
+            client_id: 0,
+            position: (0.0, 0.0, 0.0),
+            rotation_y: 0.0,
+            rotation_x: 0.0,
+            state: 0,
+            chunk_demand: vec![],
+        })
+        .encode();
+        data.extend([0; 8]);
+        assert!(Message::decode(&data).is_err());
+        // player count larger than the message
+        let mut data = vec![DataIdentifier::PlayerData as u8];
+        data.extend([0; 16]);
+        data.extend(u32::MAX.to_le_bytes());
+        assert_eq!(
+            Message::decode(&data),
+            Err(DecodeError::InvalidValue("player count"))
+        );
+        // NaN position
+        let mut data = Message::Keepalive { seq: 0 }.encode();
+        data[0] = DataIdentifier::ClientData as u8;
+        data.extend(f32::NAN.to_le_bytes());
+        data.extend([0; 24]);
+        assert_eq!(Message::decode(&data), Err(DecodeError::NonFinite(5)));
+        // absurd chunk demand
+        let mut data = Message::ClientData(ClientData {
+            client_id: 0,
+            position: (0.0, 0.0, 0.0),
+            rotation_y: 0.0,
+            rotation_x: 0.0,
+            state: 0,
+            chunk_demand: vec![],
+        })
+        .encode();
+        data.extend(vec![0; 12 * (MAX_CHUNK_DEMAND + 1)]);
+        assert_eq!(
+            Message::decode(&data),
+            Err(DecodeError::TooMany(
+                "chunk demand entries",
+                MAX_CHUNK_DEMAND + 1
+            ))
+        );
+        assert!(Message::decode(&[DataIdentifier::Chat as u8, 9, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(Message::decode(&[DataIdentifier::Chat as u8, 0, 0, 0, 0, 0, 1, 0, 0xff]).is_err());
+    }
+}
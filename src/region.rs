@@ -0,0 +1,210 @@
+// src/region.rs
+// Chunk persistence in region files. Each world stores its chunks in CONFIG.world_dir/<world name>/, one file
+// per REGION_SIZE x REGION_SIZE chunks named r.<region x>.<region z>.region. Chunks are loaded before
+// generating new ones and modified chunks are written back by the world save task and on shutdown.
+//
+// region file: [magic "VXRG"][format version (u16)][offset table][chunk records]...
+// offset table: REGION_SIZE * REGION_SIZE entries of [offset (u32)][length (u32)] indexed by
+//               local z * REGION_SIZE + local x, offset 0 is a chunk that was never stored
+// chunk record: [chunk format version (u8)][compression (u8)][payload, see Chunk::to_section_bytes]
+//
+// Records are always appended and written before the offset table entry pointing at them, so a crash
+// leaves the previous record of a rewritten chunk intact. The old record becomes unused space.
+
+use crate::chunk::Chunk;
+use crate::compression::Compression;
+use crate::metrics::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const REGION_SIZE: i32 = 8; // chunks per region along x and z
+pub const REGION_FORMAT_VERSION: u16 = 1;
+pub const CHUNK_FORMAT_VERSION: u8 = 1;
+
+const MAGIC: &[u8; 4] = b"VXRG";
+const TABLE_ENTRIES: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const HEADER_SIZE: usize = 4 + 2 + TABLE_ENTRIES * 8;
+const STORAGE_COMPRESSION: Compression = Compression::Zstd;
+
+pub struct RegionStorage {
+    dir: PathBuf,
+    lock: Mutex<()>, // region files are read and written by one caller at a time
+}
+
+impl RegionStorage {
+    pub fn new(dir: PathBuf) -> Self {
+        RegionStorage {
+            dir,
+            lock: Mutex::new(()),
+        }
+    }
+
+    // the stored chunk, None if it was never stored
+    pub fn load_chunk(&self, x: i32, z: i32) -> io::Result<Option<Chunk>> {
+        let _lock = self.lock.lock().unwrap();
+        let mut file = match File::open(self.region_path(x, z)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let header = read_header(&mut file)?;
+        let (offset, length) = table_entry(&header, table_index(x, z));
+        if offset == 0 {
+            return Ok(None);
+        }
+        let mut record = vec![0u8; length as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut record)?;
+
+        if record.len() < 2 {
+            return Err(invalid_data("chunk record too short"));
+        }
+        if record[0] != CHUNK_FORMAT_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported chunk format version {}",
+                record[0]
+            )));
+        }
+        let compression =
+            Compression::from_u8(record[1]).ok_or_else(|| invalid_data("unknown compression"))?;
+        let data = compression.decode(&record[2..])?;
+        let chunk = Chunk::from_section_bytes(x, z, &data)
+            .ok_or_else(|| invalid_data("malformed chunk data"))?;
+        //metrics
+        CHUNKS_LOADED_TOTAL.inc();
+        Ok(Some(chunk))
+    }
+
+    // writes a chunk given as Chunk::to_section_bytes
+    pub fn save_chunk(&self, x: i32, z: i32, section_bytes: &[u8]) -> io::Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.region_path(x, z))?;
+        let mut header = if file.metadata()?.len() == 0 {
+            let mut header = vec![0u8; HEADER_SIZE];
+            header[0..4].copy_from_slice(MAGIC);
+            header[4..6].copy_from_slice(&REGION_FORMAT_VERSION.to_le_bytes());
+            file.write_all(&header)?;
+            header
+        } else {
+            read_header(&mut file)?
+        };
+
+        let (compression, payload) = match STORAGE_COMPRESSION.encode(section_bytes) {
+            Some(compressed) if compressed.len() < section_bytes.len() => {
+                (STORAGE_COMPRESSION, compressed)
+            }
+            _ => (Compression::None, section_bytes.to_vec()),
+        };
+        let mut record = vec![CHUNK_FORMAT_VERSION, compression as u8];
+        record.extend(payload);
+
+        let index = table_index(x, z);
+        let offset = file.seek(SeekFrom::End(0))?;
+        let offset = u32::try_from(offset).map_err(|_| invalid_data("region file too large"))?;
+        file.write_all(&record)?;
+        file.sync_data()?;
+
+        let entry = 6 + index * 8;
+        header[entry..entry + 4].copy_from_slice(&offset.to_le_bytes());
+        header[entry + 4..entry + 8].copy_from_slice(&(record.len() as u32).to_le_bytes());
+        file.seek(SeekFrom::Start(entry as u64))?;
+        file.write_all(&header[entry..entry + 8])?;
+        file.sync_data()?;
+        //metrics
+        CHUNKS_SAVED_TOTAL.inc();
+        Ok(())
+    }
+
+    fn region_path(&self, x: i32, z: i32) -> PathBuf {
+        self.dir.join(format!(
+            "r.{}.{}.region",
+            x.div_euclid(REGION_SIZE),
+            z.div_euclid(REGION_SIZE)
+        ))
+    }
+}
+
+fn read_header(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut header = vec![0u8; HEADER_SIZE];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(invalid_data("not a region file"));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != REGION_FORMAT_VERSION {
+        return Err(invalid_data(&format!(
+            "unsupported region format version {}",
+            version
+        )));
+    }
+    Ok(header)
+}
+
+// offset and length of a chunk record
+fn table_entry(header: &[u8], index: usize) -> (u32, u32) {
+    let entry = 6 + index * 8;
+    let offset = u32::from_le_bytes(header[entry..entry + 4].try_into().unwrap());
+    let length = u32::from_le_bytes(header[entry + 4..entry + 8].try_into().unwrap());
+    (offset, length)
+}
+
+fn table_index(x: i32, z: i32) -> usize {
+    (z.rem_euclid(REGION_SIZE) * REGION_SIZE + x.rem_euclid(REGION_SIZE)) as usize
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::GeneratorSettings;
+
+    fn voxel_ids(chunk: &Chunk) -> Vec<u8> {
+        chunk.voxels.iter().map(|voxel| voxel.id).collect()
+    }
+
+    #[test]
+    fn chunks_round_trip_through_region_files() {
+        let dir = std::env::temp_dir().join(format!("voxel_region_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = RegionStorage::new(dir.clone());
+        let generator = GeneratorSettings::default();
+        let region_path = dir.join("r.0.0.region");
+
+        let mut chunk = Chunk::new(0, 0, &generator);
+        storage.save_chunk(0, 0, &chunk.to_section_bytes()).unwrap();
+        let loaded = storage.load_chunk(0, 0).unwrap().unwrap();
+        assert_eq!(voxel_ids(&loaded), voxel_ids(&chunk));
+        assert!(storage.load_chunk(1, 0).unwrap().is_none());
+
+        // a rewritten chunk is appended, the old record stays in the file
+        let size = fs::metadata(&region_path).unwrap().len();
+        chunk.set_voxel(0, 7).unwrap();
+        storage.save_chunk(0, 0, &chunk.to_section_bytes()).unwrap();
+        assert!(fs::metadata(&region_path).unwrap().len() > size);
+        let loaded = storage.load_chunk(0, 0).unwrap().unwrap();
+        assert_eq!(voxel_ids(&loaded), voxel_ids(&chunk));
+
+        // a second chunk in the same region
+        let other = Chunk::new(1, 0, &generator);
+        storage.save_chunk(1, 0, &other.to_section_bytes()).unwrap();
+        let loaded = storage.load_chunk(1, 0).unwrap().unwrap();
+        assert_eq!(loaded.coords, (1, 0));
+        assert_eq!(voxel_ids(&loaded), voxel_ids(&other));
+        let loaded = storage.load_chunk(0, 0).unwrap().unwrap();
+        assert_eq!(voxel_ids(&loaded), voxel_ids(&chunk));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+// src/registry.rs
+// Named worlds hosted by the server, configured with VOXEL_WORLDS as a comma separated list of
+// name:seed[:amplitude[:frequency]], the first one is where new players spawn. Each world has its own chunks,
+// players, entities and generation task, the world update task updates all of them every tick.
+// Players move between worlds with ChangeWorld (or the console), which unloads everything of the old world
+// on the client and sends the new one.
+
+use crate::chunk::GeneratorSettings;
+use crate::client::Client;
+use crate::config::CONFIG;
+use crate::movement::Movement;
+use crate::outbound::Priority;
+use crate::protocol::Message;
+use crate::region::RegionStorage;
+use crate::world::{Player, World};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+
+pub struct WorldRegistry {
+    pub worlds: HashMap<String, Arc<RwLock<World>>>,
+    pub default_world: String, // new players spawn here
+}
+
+impl WorldRegistry {
+    // creates every configured world, Err holds the reason the configuration is invalid
+    pub fn from_config(worlds: &str) -> Result<Self, String> {
+        let mut registry = WorldRegistry {
+            worlds: HashMap::new(),
+            default_world: String::new(),
+        };
+        for entry in worlds
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (name, generator) = parse_world(entry)?;
+            if registry.worlds.contains_key(&name) {
+                return Err(format!("world {} is configured twice", name));
+            }
+            println!("Creating world {} (seed {})", name, generator.seed);
+            if registry.default_world.is_empty() {
+                registry.default_world = name.clone();
+            }
+            // chunks of each world are stored in their own directory
+            let storage = (!CONFIG.world_dir.is_empty())
+                .then(|| Arc::new(RegionStorage::new(Path::new(&CONFIG.world_dir).join(&name))));
+            let world = World::new(name.clone(), generator, storage);
+            registry.worlds.insert(name, Arc::new(RwLock::new(world)));
+        }
+        if registry.worlds.is_empty() {
+            return Err("no worlds configured".to_string());
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<RwLock<World>>> {
+        self.worlds.get(name).cloned()
+    }
+
+    pub fn default_world(&self) -> Arc<RwLock<World>> {
+        self.worlds[&self.default_world].clone()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.worlds.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+// name:seed[:amplitude[:frequency]]
+fn parse_world(entry: &str) -> Result<(String, GeneratorSettings), String> {
+    let parts: Vec<&str> = entry.split(':').collect();
+    let invalid = || {
+        format!(
+            "invalid world {}, expected name:seed[:amplitude[:frequency]]",
+            entry
+        )
+    };
+    if parts.len() < 2 || parts.len() > 4 || parts[0].is_empty() {
+        return Err(invalid());
+    }
+    let mut generator = GeneratorSettings {
+        seed: parts[1].parse().map_err(|_| invalid())?,
+        ..GeneratorSettings::default()
+    };
+    if let Some(amplitude) = parts.get(2) {
+        generator.amplitude = amplitude.parse().map_err(|_| invalid())?;
+    }
+    if let Some(frequency) = parts.get(3) {
+        generator.frequency = frequency.parse().map_err(|_| invalid())?;
+    }
+    Ok((parts[0].to_string(), generator))
+}
+
+// moves a client to another world: it leaves the old world's players, everything the client got from the old
+// world is unloaded and it is placed at the new world's spawn, where chunks, players and entities are sent anew
+pub async fn switch_world(client: &Arc<RwLock<Client>>, target: &Arc<RwLock<World>>) {
+    let current = client.read().await.world.clone();
+    if Arc::ptr_eq(&current, target) {
+        return;
+    }
+    let (name, spawn_point) = {
+        let target = target.read().await;
+        (target.name.clone(), target.spawn_point())
+    };
+
+    // lock order world -> client, the two worlds are never locked together
+    let player = {
+        let mut world = current.write().await;
+        let mut client = client.write().await;
+        // switched by someone else in the meantime
+        if !Arc::ptr_eq(&client.world, &current) {
+            return;
+        }
+        world.players.remove(&client.id);
+
+        // queued chunks were requested in the old world
+        client.outbound.clear_chunks();
+        client.queued_chunks.clear();
+        client.chunk_demand.clear();
+        let mut loaded_chunks: Vec<(i32, i32)> = client.loaded_chunks.drain().collect();
+        loaded_chunks.extend(client.sending_chunks.drain());
+        for (x, z) in loaded_chunks {
+            client
+                .outbound
+                .push_data(Priority::Bulk, Message::ChunkUnload { x, z }.encode());
+        }
+        let visible_players: Vec<u32> = client.visible_players.drain().collect();
+        for player_id in visible_players {
+            let data = Message::PlayerLeaveView { player_id }.encode();
+            client.outbound.push_data(Priority::Entity, data);
+        }
+        let visible_entities: Vec<u32> = client.visible_entities.drain().collect();
+        for entity_id in visible_entities {
+            let data = Message::EntityDespawn { entity_id }.encode();
+            client.outbound.push_data(Priority::Entity, data);
+        }
+
+        client.world = target.clone();
+        client.position = spawn_point;
+        client.movement = Movement::new();
+        client.movement_check.position = spawn_point;
+        client.movement_check.accepted_at = Instant::now();
+        // positions from the old world still in flight are dropped without counting as violations
+        client.movement_check.reset_at = Some(Instant::now());
+        let data = Message::ChangeWorld {
+            name: name.clone(),
+            position: spawn_point,
+        }
+        .encode();
+        // ahead of the unloads, so the client knows they belong to the world it left
+        client.outbound.push_data(Priority::Control, data);
+        println!("client_id:{} moved to world {}", client.id, name);
+        Player::new(client.id, client.position, client.rotation, client.state)
+    };
+    // a client disconnecting meanwhile was already removed from the target, it must not be added back
+    let mut world = target.write().await;
+    let client = client.read().await;
+    if Arc::ptr_eq(&client.world, target) && !client.outbound.is_closed() && !client.is_spectator()
+    {
+        world.add_player(player);
+    }
+}
@@ -0,0 +1,369 @@
+// src/session.rs
+// Client session lifecycle: disconnect reasons and the single cleanup path every disconnect goes through.
+// Disconnect messages go both ways and carry a reason code and a message.
+// Sessions lost to a dropped connection or timeout are suspended for CONFIG.session_grace_ms,
+// a client sending the session token in its hello within that time gets the session back.
+
+use crate::anticheat::MovementCheck;
+use crate::chat::broadcast_system;
+use crate::client::{Client, ClientManager};
+use crate::config::{ViolationPolicy, CONFIG};
+use crate::metrics::*;
+use crate::outbound::Priority;
+use crate::protocol::Message;
+use crate::world::World;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum DisconnectReason {
+    Quit = 0,
+    Kicked = 1,
+    Timeout = 2,
+    ProtocolError = 3,
+    ServerShutdown = 4,
+    ConnectionLost = 5, // socket closed or failed without a disconnect message
+}
+
+impl DisconnectReason {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(DisconnectReason::Quit),
+            1 => Some(DisconnectReason::Kicked),
+            2 => Some(DisconnectReason::Timeout),
+            3 => Some(DisconnectReason::ProtocolError),
+            4 => Some(DisconnectReason::ServerShutdown),
+            5 => Some(DisconnectReason::ConnectionLost),
+            _ => None,
+        }
+    }
+
+    // the client did not mean to leave, so its session is kept for a while
+    pub fn is_resumable(self) -> bool {
+        matches!(
+            self,
+            DisconnectReason::Timeout | DisconnectReason::ConnectionLost
+        )
+    }
+}
+
+// what is kept of a lost session until it is resumed or expires
+pub struct SuspendedSession {
+    pub client_id: u32,
+    pub session_token: u64,
+    pub position: (f32, f32, f32),
+    pub rotation: (f32, f32, f32),
+    pub state: u32,
+    pub world: Arc<RwLock<World>>,
+    pub loaded_chunks: HashSet<(i32, i32)>, // chunks the client still holds, not sent again
+    pub movement_score: u32,
+    pub suspended_at: Instant,
+    pub synced_tick: u64, // chunks changed since then may not be up to date on the client
+}
+
+impl SuspendedSession {
+    pub fn from_client(client: &Client) -> Self {
+        SuspendedSession {
+            client_id: client.id,
+            session_token: client.session_token,
+            position: client.position,
+            rotation: client.rotation,
+            state: client.state,
+            world: client.world.clone(),
+            // chunks still being written are not known to have arrived
+            loaded_chunks: client.loaded_chunks.clone(),
+            movement_score: client.movement_check.score,
+            suspended_at: Instant::now(),
+            synced_tick: client.synced_tick,
+        }
+    }
+
+    // forgets loaded chunks that changed after the client's outbound queue was last written out,
+    // while it was suspended or with a VoxelChanged that was still queued. they have to be sent again
+    pub fn drop_changed_chunks(&mut self, world: &World) {
+        let synced_tick = self.synced_tick;
+        self.loaded_chunks.retain(|coords| {
+            world
+                .chunk_changed_at
+                .get(coords)
+                .is_none_or(|&changed_at| changed_at < synced_tick)
+        });
+    }
+
+    // puts the session's state back on a newly connected client
+    pub fn restore(mut self, client: &mut Client, world: &World) {
+        self.drop_changed_chunks(world);
+        client.id = self.client_id;
+        client.session_token = self.session_token;
+        client.position = self.position;
+        client.rotation = self.rotation;
+        client.state = self.state;
+        client.world = self.world;
+        client.loaded_chunks = self.loaded_chunks;
+        client.movement_check = MovementCheck::new(self.position);
+        client.movement_check.score = self.movement_score;
+    }
+}
+
+// Removes the client and its player, stops the connection's tasks and tells the other clients.
+// Safe to call more than once, only the first call does anything.
+pub async fn disconnect_client(
+    client: &Arc<RwLock<Client>>,
+    reason: DisconnectReason,
+    message: &str,
+    client_manager: &Arc<RwLock<ClientManager>>,
+) {
+    let (client_id, world, is_spectator, suspended) = {
+        // write lock so concurrent calls can't both see the queue open
+        let client = client.write().await;
+        // the closed outbound queue marks a client that is already disconnecting
+        if client.outbound.is_closed() {
+            return;
+        }
+        // the client already left when it quit or lost the connection, anything else is explained to it
+        let final_data = match reason {
+            DisconnectReason::Quit | DisconnectReason::ConnectionLost => None,
+            _ => Some(
+                Message::Disconnect {
+                    reason,
+                    message: message.to_string(),
+                }
+                .encode(),
+            ),
+        };
+        // writer task sends the final message, then closes the socket
+        client.outbound.close_with(final_data);
+        client.shutdown.notify_one();
+        // a spectator has nothing worth resuming
+        let suspended =
+            (reason.is_resumable() && CONFIG.session_grace_ms > 0 && !client.is_spectator())
+                .then(|| SuspendedSession::from_client(&client));
+        (
+            client.id,
+            client.world.clone(),
+            client.is_spectator(),
+            suspended,
+        )
+    };
+    println!(
+        "Client disconnected client_id:{} reason:{:?} {}",
+        client_id, reason, message
+    );
+
+    // a suspended player only leaves for the others once its session expires
+    let left = {
+        let mut manager = client_manager.write().await;
+        match suspended {
+            Some(session) => {
+                println!("Session of client_id:{} suspended", client_id);
+                tokio::spawn(expire_session(
+                    session.session_token,
+                    client_manager.clone(),
+                ));
+                manager.suspend_client(session);
+                false
+            }
+            None => {
+                manager.remove_client(client_id);
+                true
+            }
+        }
+    };
+    if left && !is_spectator && reason != DisconnectReason::ServerShutdown {
+        broadcast_system(&format!("player {} left", client_id), client_manager).await;
+    }
+    world.write().await.players.remove(&client_id);
+    //metrics
+    CLIENT_COUNT.dec();
+
+    // tell everyone who could see the player that it left
+    let manager = client_manager.read().await;
+    for client_arc in manager.clients.values() {
+        let mut other = client_arc.write().await;
+        if other.visible_players.remove(&client_id) {
+            other.outbound.push_data(
+                Priority::Entity,
+                Message::PlayerLeaveView {
+                    player_id: client_id,
+                }
+                .encode(),
+            );
+        }
+    }
+}
+
+// forgets the session once the grace period is over, unless it was resumed meanwhile
+async fn expire_session(session_token: u64, client_manager: Arc<RwLock<ClientManager>>) {
+    let grace = Duration::from_millis(CONFIG.session_grace_ms);
+    tokio::time::sleep(grace).await;
+    let expired = client_manager
+        .write()
+        .await
+        .expire_session(session_token, grace);
+    if let Some(client_id) = expired {
+        broadcast_system(&format!("player {} left", client_id), &client_manager).await;
+    }
+}
+
+// takes over the session of the token, None if there is nothing to resume.
+// a session whose old connection still looks alive is suspended first, the new connection wins
+pub async fn resume_session(
+    session_token: u64,
+    client_manager: &Arc<RwLock<ClientManager>>,
+) -> Option<SuspendedSession> {
+    if CONFIG.session_grace_ms == 0 {
+        return None;
+    }
+    let old_client = {
+        let manager = client_manager.read().await;
+        manager
+            .sessions
+            .get(&session_token)
+            .and_then(|client_id| manager.clients.get(client_id).cloned())
+    };
+    if let Some(old_client) = old_client {
+        disconnect_client(
+            &old_client,
+            DisconnectReason::ConnectionLost,
+            "session resumed from a new connection",
+            client_manager,
+        )
+        .await;
+    }
+    client_manager.write().await.resume_session(session_token)
+}
+
+// counts an invalid message against the client, returns true when it should be kicked
+pub async fn record_violation(client: &Arc<RwLock<Client>>, kind: &str, detail: &str) -> bool {
+    //metrics
+    REJECTED_MESSAGES_TOTAL.with_label_values(&[kind]).inc();
+    let mut client = client.write().await;
+    client.violations += 1;
+    if CONFIG.violation_policy == ViolationPolicy::Ignore {
+        return false;
+    }
+    println!(
+        "Rejected message from client_id:{} ({} violations): {}",
+        client.id, client.violations, detail
+    );
+    CONFIG.violation_policy == ViolationPolicy::Kick
+        && client.violations >= CONFIG.violation_kick_threshold
+}
+
+// disconnects every client, used when the server shuts down
+pub async fn disconnect_all(client_manager: &Arc<RwLock<ClientManager>>) {
+    let clients: Vec<Arc<RwLock<Client>>> = {
+        let manager = client_manager.read().await;
+        manager.clients.values().cloned().collect()
+    };
+    for client in clients {
+        disconnect_client(
+            &client,
+            DisconnectReason::ServerShutdown,
+            "server shutting down",
+            client_manager,
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::GeneratorSettings;
+    use crate::clock::{advance_tick, current_tick};
+    use crate::movement::Movement;
+    use crate::outbound::OutboundQueue;
+    use std::collections::VecDeque;
+    use tokio::sync::Notify;
+
+    fn test_world() -> Arc<RwLock<World>> {
+        Arc::new(RwLock::new(World::new(
+            "test".to_string(),
+            GeneratorSettings::default(),
+            None,
+        )))
+    }
+
+    fn test_client(world: Arc<RwLock<World>>) -> Client {
+        Client {
+            id: 1,
+            session_token: 1,
+            position: (0.0, 0.0, 0.0),
+            rotation: (0.0, 0.0, 0.0),
+            state: 0,
+            capabilities: 0,
+            chunk_demand: vec![],
+            loaded_chunks: HashSet::new(),
+            queued_chunks: HashSet::new(),
+            sending_chunks: HashSet::new(),
+            visible_players: HashSet::new(),
+            visible_entities: HashSet::new(),
+            outbound: Arc::new(OutboundQueue::new()),
+            shutdown: Arc::new(Notify::new()),
+            last_seen: Instant::now(),
+            synced_tick: current_tick(),
+            keepalive_seq: 0,
+            keepalive_sent: None,
+            rtt_ms: 0.0,
+            udp: None,
+            chat_sent: VecDeque::new(),
+            violations: 0,
+            movement: Movement::new(),
+            movement_check: MovementCheck::new((0.0, 0.0, 0.0)),
+            world,
+            packet_count_rx: 0,
+        }
+    }
+
+    #[test]
+    fn chunks_not_fully_sent_are_not_kept() {
+        let world = test_world();
+        let mut world_guard = world.try_write().unwrap();
+        let mut client = test_client(world.clone());
+        client.start_chunk_send(0, 0);
+        client.chunk_sent(0, 0);
+        client.start_chunk_send(1, 1);
+        client.chunk_sent(1, 1);
+        // the connection dropped while this chunk was written
+        client.start_chunk_send(1, 0);
+        client.synced_tick = current_tick();
+        advance_tick();
+        // its VoxelChanged was still queued when the connection dropped
+        world_guard.set_voxel_at(70, 50, 70, 0).unwrap();
+
+        let mut session = SuspendedSession::from_client(&client);
+        session.drop_changed_chunks(&world_guard);
+        assert_eq!(session.loaded_chunks, HashSet::from([(0, 0)]));
+    }
+
+    #[test]
+    fn chunks_changed_while_suspended_are_not_kept() {
+        let world = test_world();
+        let mut world_guard = world.try_write().unwrap();
+        // changed before the session was suspended, the client got the VoxelChanged
+        world_guard.set_voxel_at(70, 50, 5, 0).unwrap();
+        advance_tick();
+
+        let mut session = SuspendedSession {
+            client_id: 1,
+            session_token: 1,
+            position: (0.0, 0.0, 0.0),
+            rotation: (0.0, 0.0, 0.0),
+            state: 0,
+            world: world.clone(),
+            loaded_chunks: HashSet::from([(0, 0), (1, 0), (1, 1)]),
+            movement_score: 0,
+            suspended_at: Instant::now(),
+            synced_tick: current_tick(),
+        };
+        // changed during the grace period
+        world_guard.set_voxel_at(5, 50, 5, 0).unwrap();
+
+        session.drop_changed_chunks(&world_guard);
+        assert_eq!(session.loaded_chunks, HashSet::from([(1, 0), (1, 1)]));
+    }
+}
@@ -0,0 +1,115 @@
+// src/tls.rs
+// Optional TLS for the TCP and WebSocket listeners. Certificate chain and private key are read from PEM files,
+// self-signed certificates work as well. The files are checked for changes every CONFIG.tls_reload_interval_ms
+// and rotated certificates are used for new connections, open connections keep the one they started with.
+
+use crate::config::CONFIG;
+use crate::transport::BoxedStream;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+
+pub struct TlsConfig {
+    server_config: RwLock<Arc<rustls::ServerConfig>>,
+    modified: Mutex<Option<SystemTime>>, // newest modification time of the loaded files
+}
+
+impl TlsConfig {
+    pub fn load() -> Result<Self, String> {
+        let modified = files_modified();
+        let server_config = load_server_config(&CONFIG.tls_cert_path, &CONFIG.tls_key_path)?;
+        Ok(TlsConfig {
+            server_config: RwLock::new(Arc::new(server_config)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.server_config.read().unwrap().clone())
+    }
+
+    // reloads the certificate when the files changed, a broken new certificate keeps the old one in use
+    pub fn reload_if_changed(&self) {
+        let modified = files_modified();
+        let mut loaded = self.modified.lock().unwrap();
+        if modified.is_none() || modified == *loaded {
+            return;
+        }
+        match load_server_config(&CONFIG.tls_cert_path, &CONFIG.tls_key_path) {
+            Ok(server_config) => {
+                *self.server_config.write().unwrap() = Arc::new(server_config);
+                println!("TLS certificate reloaded from {}", CONFIG.tls_cert_path);
+            }
+            Err(e) => eprintln!(
+                "Failed to reload TLS certificate, keeping the old one: {}",
+                e
+            ),
+        }
+        // a broken file is not retried until it changes again
+        *loaded = modified;
+    }
+}
+
+fn files_modified() -> Option<SystemTime> {
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    modified(&CONFIG.tls_cert_path).max(modified(&CONFIG.tls_key_path))
+}
+
+pub fn load_server_config(cert_path: &str, key_path: &str) -> Result<rustls::ServerConfig, String> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("{}: {}", path, e))
+    };
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", cert_path));
+    }
+    let key = rustls_pemfile::private_key(&mut open(key_path)?)
+        .map_err(|e| format!("{}: {}", key_path, e))?
+        .ok_or_else(|| format!("{}: no private key found", key_path))?;
+
+    rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("{}: {}", cert_path, e))
+}
+
+pub async fn tls_reload_task(tls: Arc<TlsConfig>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(CONFIG.tls_reload_interval_ms));
+    loop {
+        interval.tick().await;
+        tls.reload_if_changed();
+    }
+}
+
+// wraps an accepted connection in TLS when the listener has it enabled
+pub async fn accept_stream(
+    stream: TcpStream,
+    tls: Option<&TlsConfig>,
+) -> std::io::Result<BoxedStream> {
+    let Some(tls) = tls else {
+        return Ok(Box::new(stream));
+    };
+    let handshake = tls.acceptor().accept(stream);
+    match tokio::time::timeout(
+        Duration::from_millis(CONFIG.handshake_timeout_ms),
+        handshake,
+    )
+    .await
+    {
+        Ok(stream) => Ok(Box::new(stream?)),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "TLS handshake timed out",
+        )),
+    }
+}
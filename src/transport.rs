@@ -0,0 +1,127 @@
+// src/transport.rs
+// Reading and writing protocol frames over either a raw stream or a WebSocket,
+// so both connection types share the same session logic.
+// Streams are plain TCP or TLS over TCP, see tls.rs.
+// On WebSocket every binary message carries exactly one frame, messages larger than the max frame size
+// are refused by tungstenite before they are buffered.
+
+use crate::codec::{FrameDecoder, FrameError};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio_tungstenite::tungstenite::error::{CapacityError, Error as WsError};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+// any byte stream a connection can run over
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+pub type BoxedStream = Box<dyn Stream>;
+
+// what a read from the connection produced
+pub enum Incoming {
+    Frame(Vec<u8>),
+    Ping,   // WebSocket ping or pong, only shows the peer is alive
+    Closed, // the peer closed the connection on purpose (WebSocket close frame)
+    Ended,  // the stream ended without a close
+}
+
+// limits a WebSocket message, which carries one frame, to the max frame size
+pub fn websocket_config(max_frame_size: usize) -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(max_frame_size),
+        max_frame_size: Some(max_frame_size),
+        ..WebSocketConfig::default()
+    }
+}
+
+pub enum FrameReader {
+    Tcp(ReadHalf<BoxedStream>, FrameDecoder),
+    WebSocket(SplitStream<WebSocketStream<BoxedStream>>, FrameDecoder),
+}
+
+impl FrameReader {
+    // frames written straight to the stream
+    pub fn tcp(stream: BoxedStream, max_frame_size: usize) -> (FrameReader, FrameWriter) {
+        let (read_half, write_half) = tokio::io::split(stream);
+        (
+            FrameReader::Tcp(read_half, FrameDecoder::new(max_frame_size)),
+            FrameWriter::Tcp(write_half),
+        )
+    }
+
+    pub fn websocket(
+        ws_stream: WebSocketStream<BoxedStream>,
+        max_frame_size: usize,
+    ) -> (FrameReader, FrameWriter) {
+        let (ws_tx, ws_rx) = ws_stream.split();
+        (
+            FrameReader::WebSocket(ws_rx, FrameDecoder::new(max_frame_size)),
+            FrameWriter::WebSocket(ws_tx),
+        )
+    }
+
+    // next frame without the length header, Ok(None) when the peer closed the connection
+    pub async fn read_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        loop {
+            match self.read().await? {
+                Incoming::Frame(frame) => return Ok(Some(frame)),
+                Incoming::Ping => continue,
+                Incoming::Closed | Incoming::Ended => return Ok(None),
+            }
+        }
+    }
+
+    // next frame or connection event
+    pub async fn read(&mut self) -> Result<Incoming, FrameError> {
+        match self {
+            FrameReader::Tcp(read_half, decoder) => match decoder.read_frame(read_half).await? {
+                Some(frame) => Ok(Incoming::Frame(frame)),
+                None => Ok(Incoming::Ended),
+            },
+            FrameReader::WebSocket(ws_rx, decoder) => match ws_rx.next().await {
+                Some(Ok(Message::Binary(bin))) => decoder.decode_message(&bin).map(Incoming::Frame),
+                // pings are answered by tungstenite on the next write
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => Ok(Incoming::Ping),
+                Some(Ok(Message::Close(_))) => Ok(Incoming::Closed),
+                None => Ok(Incoming::Ended),
+                // a protocol violation by the client, not a lost connection
+                Some(Ok(Message::Text(_))) => Err(FrameError::UnexpectedMessage("WebSocket text")),
+                Some(Ok(Message::Frame(_))) => {
+                    Err(FrameError::UnexpectedMessage("WebSocket raw frame"))
+                }
+                // oversized messages are a malformed client, not a lost connection
+                Some(Err(WsError::Capacity(CapacityError::MessageTooLong { size, max_size }))) => {
+                    Err(FrameError::TooLarge(size, max_size))
+                }
+                Some(Err(e)) => Err(FrameError::Io(std::io::Error::other(e))),
+            },
+        }
+    }
+}
+
+pub enum FrameWriter {
+    Tcp(WriteHalf<BoxedStream>),
+    WebSocket(SplitSink<WebSocketStream<BoxedStream>, Message>),
+}
+
+impl FrameWriter {
+    // writes one encoded frame (length header included)
+    pub async fn write_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        match self {
+            FrameWriter::Tcp(write_half) => write_half.write_all(frame).await,
+            FrameWriter::WebSocket(ws_tx) => ws_tx
+                .send(Message::Binary(frame.to_vec()))
+                .await
+                .map_err(std::io::Error::other),
+        }
+    }
+
+    pub async fn close(&mut self) {
+        let _ = match self {
+            FrameWriter::Tcp(write_half) => write_half.shutdown().await,
+            FrameWriter::WebSocket(ws_tx) => ws_tx.close().await.map_err(std::io::Error::other),
+        };
+    }
+}
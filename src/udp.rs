@@ -0,0 +1,140 @@
+// src/udp.rs
+// Optional unreliable channel for movement updates, inputs, movement corrections and player snapshots.
+// Datagrams carry the udp token from UdpToken, which is issued per connection and unlike the session token
+// can't be used to resume the session. The first datagram with a valid token binds the client's UDP address.
+// Client -> server datagram: [token (u64)][sequence (u32)][message]
+// Server -> client datagram: [sequence (u32)][message]
+// Datagrams with a sequence number not newer than the last one received are dropped as stale, the rest are
+// processed one after another in the receive loop so their updates apply in sequence order.
+
+use crate::client::{Client, ClientManager};
+use crate::data::{process_client_data, process_input};
+use crate::metrics::*;
+use crate::protocol::Message;
+use crate::session::{disconnect_client, record_violation, DisconnectReason};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+
+const CLIENT_HEADER_SIZE: usize = 12; // token + sequence
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+pub struct UdpChannel {
+    pub socket: Arc<UdpSocket>,
+    pub addr: SocketAddr,
+    pub seq_tx: u32,
+    pub seq_rx: u32,
+}
+
+impl UdpChannel {
+    // sends data without waiting, a full socket buffer just drops the datagram
+    pub fn send(&mut self, data: &[u8]) {
+        self.seq_tx = self.seq_tx.wrapping_add(1);
+        let mut datagram = Vec::with_capacity(data.len() + 4);
+        datagram.extend(self.seq_tx.to_le_bytes());
+        datagram.extend_from_slice(data);
+        if datagram.len() > MAX_DATAGRAM_SIZE {
+            return;
+        }
+        if self.socket.try_send_to(&datagram, self.addr).is_ok() {
+            //metrics
+            NETWORK_BYTES_EGRESS_TOTAL.inc_by(datagram.len() as u64);
+        }
+    }
+}
+
+// newer than last, allowing the sequence number to wrap around
+fn is_newer(seq: u32, last: u32) -> bool {
+    (seq.wrapping_sub(last) as i32) > 0
+}
+
+// receives every datagram on the shared UDP socket and routes it to the client owning the token
+pub async fn udp_receive_task(socket: Arc<UdpSocket>, client_manager: Arc<RwLock<ClientManager>>) {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let (length, addr) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Failed to receive UDP datagram: {:?}", e);
+                continue;
+            }
+        };
+        //metrics
+        NETWORK_BYTES_INGRESS_TOTAL.inc_by(length as u64);
+        if length <= CLIENT_HEADER_SIZE {
+            continue;
+        }
+        let token = u64::from_le_bytes(buffer[0..8].try_into().unwrap());
+        let seq = u32::from_le_bytes(buffer[8..12].try_into().unwrap());
+
+        let client: Option<Arc<RwLock<Client>>> = {
+            let manager = client_manager.read().await;
+            manager
+                .udp_tokens
+                .get(&token)
+                .and_then(|client_id| manager.clients.get(client_id).cloned())
+        };
+        let Some(client) = client else {
+            continue;
+        };
+
+        let world = {
+            let mut client = client.write().await;
+            match &mut client.udp {
+                Some(udp) => {
+                    if !is_newer(seq, udp.seq_rx) {
+                        continue;
+                    }
+                    udp.seq_rx = seq;
+                    // follow the client if its address changed (NAT rebinding)
+                    udp.addr = addr;
+                }
+                None => {
+                    println!("UDP bound for client_id:{} from {}", client.id, addr);
+                    client.udp = Some(UdpChannel {
+                        socket: socket.clone(),
+                        addr,
+                        seq_tx: 0,
+                        seq_rx: seq,
+                    });
+                }
+            }
+            client.last_seen = Instant::now();
+            client.world.clone()
+        };
+
+        // only movement goes over UDP, everything else must use TCP
+        let (kind, detail) = match Message::decode(&buffer[CLIENT_HEADER_SIZE..length]) {
+            // inline, positions are validated against the previous one
+            Ok(Message::ClientData(client_data)) => {
+                process_client_data(client_data, client, world).await;
+                continue;
+            }
+            Ok(Message::Input(input)) => {
+                if process_input(input, &client).await {
+                    continue;
+                }
+                (
+                    "unexpected",
+                    "input without authoritative movement".to_string(),
+                )
+            }
+            Ok(other) => (
+                "unexpected",
+                format!("unexpected {:?} over UDP", other.identifier()),
+            ),
+            Err(e) => (e.kind(), e.to_string()),
+        };
+        if record_violation(&client, kind, &detail).await {
+            let message = format!("invalid messages: {}", detail);
+            let client_manager = client_manager.clone();
+            // kicked outside the receive loop, it must not wait on locks held by others
+            tokio::spawn(async move {
+                disconnect_client(&client, DisconnectReason::Kicked, &message, &client_manager)
+                    .await;
+            });
+        }
+    }
+}
@@ -1,19 +1,23 @@
 use crate::{
-    chunk::{Chunk, Voxel, CHUNK_HEIGHT, CHUNK_SIZE},
+    chunk::{Chunk, GeneratorSettings, Voxel, CHUNK_HEIGHT, CHUNK_SIZE},
     client::ClientManager,
-    data::DataIdentifier,
-    CHUNK_GENERATED_COUNTER, CHUNK_GENERATION_TIME,
+    clock::{advance_tick, current_tick, server_time_ms},
+    config::CONFIG,
+    entity::{replicate_entities, Entity, EntityKind},
+    metrics::{CHUNK_GENERATED_COUNTER, CHUNK_GENERATION_TIME},
+    movement::simulate_clients,
+    outbound::Priority,
+    protocol::Message,
+    region::RegionStorage,
+    registry::WorldRegistry,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Instant;
-use std::{
-    collections::{HashMap, HashSet},
-    thread::spawn,
-};
 use tokio::sync::RwLock;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Player {
     pub id: u32, // Unique ID for the player
     pub position: (f32, f32, f32),
@@ -32,26 +36,51 @@ impl Player {
     }
 }
 
+// positions are kept within this many blocks of the origin horizontally
+pub const MAX_HORIZONTAL_COORDINATE: f32 = 30_000_000.0;
+
 #[derive(Serialize, Deserialize)]
 pub struct World {
     pub chunks: HashMap<(i32, i32), Chunk>, // 2D map of chunks identified by their coordinates (x, z)
     pub players: HashMap<u32, Player>,      // Map of players by their unique ID
     pub spawn: (i32, i32, i32),             // Position where new client spawns
+    pub name: String,                       // name in the world registry
+    pub generator: GeneratorSettings,       // terrain of newly generated chunks
+    pub entities: HashMap<u32, Entity>,     // non-player entities by their ID
+    #[serde(skip)]
+    pub storage: Option<Arc<RegionStorage>>, // region files, None when persistence is disabled
+    #[serde(skip)]
+    pub modified_chunks: HashSet<(i32, i32)>, // chunks changed since they were last saved
+    #[serde(skip)]
+    pub chunk_changed_at: HashMap<(i32, i32), u64>, // tick of the last voxel change per chunk
+    next_entity_id: u32,
 }
 
 impl World {
-    pub fn new() -> Self {
+    pub fn new(
+        name: String,
+        generator: GeneratorSettings,
+        storage: Option<Arc<RegionStorage>>,
+    ) -> Self {
         let mut world = World {
             players: HashMap::new(),
             chunks: HashMap::new(),
             spawn: (0, 0, 0),
+            name,
+            generator,
+            entities: HashMap::new(),
+            storage,
+            modified_chunks: HashSet::new(),
+            chunk_changed_at: HashMap::new(),
+            next_entity_id: 1,
         };
 
         // generate starting chunks 3x3
         for x in 0..2 {
             for z in 0..2 {
-                let generated_chunk = Chunk::new(x, z);
-                world.chunks.insert((x, z), generated_chunk);
+                let chunk =
+                    World::load_or_generate(world.storage.as_deref(), x, z, &world.generator);
+                world.chunks.insert((x, z), chunk);
             }
         }
 
@@ -59,7 +88,7 @@ impl World {
         if let Some(spawn_chunk) = world.get_chunk(0, 0) {
             // middle index
             let mut index = CHUNK_SIZE * CHUNK_SIZE / 2 - (CHUNK_SIZE / 2 + 1);
-            for y in 0..CHUNK_HEIGHT {
+            for _ in 0..CHUNK_HEIGHT {
                 if let Some(voxel) = spawn_chunk.get_voxel(index) {
                     if voxel.id == 0 {
                         //check above voxel for air
@@ -82,10 +111,100 @@ impl World {
         world
     }
 
+    // the stored chunk, generated when it was never stored or can't be read
+    pub fn load_or_generate(
+        storage: Option<&RegionStorage>,
+        x: i32,
+        z: i32,
+        generator: &GeneratorSettings,
+    ) -> Chunk {
+        match storage.map(|storage| storage.load_chunk(x, z)) {
+            Some(Ok(Some(chunk))) => {
+                println!("Loaded chunk ({},{}) ↓", x, z);
+                return chunk;
+            }
+            Some(Err(e)) => eprintln!("Failed to load chunk ({},{}): {}", x, z, e),
+            _ => {}
+        }
+        CHUNK_GENERATED_COUNTER.inc();
+        Chunk::new(x, z, generator)
+    }
+
+    pub fn spawn_point(&self) -> (f32, f32, f32) {
+        (
+            self.spawn.0 as f32,
+            self.spawn.1 as f32,
+            self.spawn.2 as f32,
+        )
+    }
+
     pub fn add_player(&mut self, player: Player) {
         self.players.insert(player.id, player);
     }
 
+    // adds an entity and returns its id, expires_at is the tick it is removed at (None keeps it)
+    pub fn spawn_entity(
+        &mut self,
+        kind: EntityKind,
+        position: (f32, f32, f32),
+        velocity: (f32, f32, f32),
+        data: Vec<u8>,
+        expires_at: Option<u64>,
+    ) -> u32 {
+        let id = self.next_entity_id;
+        self.next_entity_id = self
+            .next_entity_id
+            .checked_add(1)
+            .expect("entity ids exhausted");
+        self.entities.insert(
+            id,
+            Entity {
+                id,
+                kind,
+                position,
+                velocity,
+                data,
+                expires_at,
+            },
+        );
+        id
+    }
+
+    // removes an entity, clients in view get EntityDespawn on the next update
+    pub fn despawn_entity(&mut self, id: u32) -> Option<Entity> {
+        self.entities.remove(&id)
+    }
+
+    // moves every entity by one tick and removes expired ones
+    pub fn update_entities(&mut self, tick: u64, dt: f32) {
+        let mut entities = std::mem::take(&mut self.entities);
+        entities.retain(|_, entity| entity.update(self, tick, dt));
+        self.entities = entities;
+    }
+
+    pub fn entities_in_range(&self, position: (f32, f32, f32), radius: i32) -> HashSet<u32> {
+        let (chunk_x, chunk_z) = World::chunk_coords_of(position);
+        self.entities
+            .values()
+            .filter(|entity| {
+                let (entity_chunk_x, entity_chunk_z) = World::chunk_coords_of(entity.position);
+                i64::from(entity_chunk_x.abs_diff(chunk_x)) <= i64::from(radius)
+                    && i64::from(entity_chunk_z.abs_diff(chunk_z)) <= i64::from(radius)
+            })
+            .map(|entity| entity.id)
+            .collect()
+    }
+
+    // snapshot of the given entities
+    pub fn entities_to_bytes(&self, entity_ids: &HashSet<u32>, tick: u64) -> Vec<u8> {
+        let entities = entity_ids
+            .iter()
+            .filter_map(|id| self.entities.get(id))
+            .map(|entity| (entity.id, entity.position, entity.velocity))
+            .collect();
+        Message::EntityUpdate { tick, entities }.encode()
+    }
+
     pub fn get_chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
         self.chunks.get(&(x, z))
     }
@@ -93,51 +212,107 @@ impl World {
     pub fn get_player(&self, id: u32) -> Option<&Player> {
         self.players.get(&id)
     }
-    pub fn players_to_bytes(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        data.resize(4, 1); // Pre-allocate length header bytes (byte index 0-3)
-        //identifier (4 bytes)
-        let data_identifier = DataIdentifier::PlayerData;
-        data.push(data_identifier as u8);
-
-        // player count (4 bytes)
-        let player_count = self.players.len() as i32;
-        data.extend(player_count.to_le_bytes());
-        // all players id,position,rotation,state (28 bytes for each player)
-        for (id,player) in self.players.clone() {
-            data.extend(id.to_le_bytes());
-            data.extend(player.position.0.to_le_bytes()); //x (4bytes)
-            data.extend(player.position.1.to_le_bytes()); //y (4bytes)
-            data.extend(player.position.2.to_le_bytes()); //z (4bytes)
-            data.extend(player.rotation.0.to_le_bytes()); //x (4bytes)
-            data.extend(player.rotation.1.to_le_bytes()); //y (4bytes)
-            data.extend(player.rotation.2.to_le_bytes()); //z (4bytes)
-            data.extend(player.state.to_le_bytes()); //state (4bytes)
+
+    // converts world voxel coordinates to chunk coordinates and the voxel index inside that chunk
+    pub fn world_to_chunk_coords(x: i32, y: i32, z: i32) -> Option<((i32, i32), usize)> {
+        let chunk_size = CHUNK_SIZE as i32;
+        let chunk_x = x.div_euclid(chunk_size);
+        let chunk_z = z.div_euclid(chunk_size);
+        let index = Chunk::coords_to_index(
+            x.rem_euclid(chunk_size),
+            y,
+            z.rem_euclid(chunk_size),
+        )?;
+        Some(((chunk_x, chunk_z), index))
+    }
+
+    // returns voxel id at world coordinates, None if chunk is not loaded or y is out of bounds
+    pub fn get_voxel_at(&self, x: i32, y: i32, z: i32) -> Option<u8> {
+        let ((chunk_x, chunk_z), index) = World::world_to_chunk_coords(x, y, z)?;
+        let chunk = self.get_chunk(chunk_x, chunk_z)?;
+        chunk.get_voxel(index).map(|voxel| voxel.id)
+    }
+
+    // sets voxel id at world coordinates, returns the previous id
+    pub fn set_voxel_at(&mut self, x: i32, y: i32, z: i32, id: u8) -> Option<u8> {
+        let ((chunk_x, chunk_z), index) = World::world_to_chunk_coords(x, y, z)?;
+        let chunk = self.chunks.get_mut(&(chunk_x, chunk_z))?;
+        let previous_id = chunk.set_voxel(index, id)?;
+        self.modified_chunks.insert((chunk_x, chunk_z));
+        self.chunk_changed_at.insert((chunk_x, chunk_z), current_tick());
+        Some(previous_id)
+    }
+
+    // snapshot of the given players, stamped with the tick and server clock it was taken at
+    pub fn players_to_bytes(
+        &self,
+        player_ids: &HashSet<u32>,
+        tick: u64,
+        server_time: u64,
+    ) -> Vec<u8> {
+        let players = player_ids
+            .iter()
+            .filter_map(|id| self.players.get(id))
+            .cloned()
+            .collect();
+        Message::PlayerData {
+            tick,
+            server_time,
+            players,
         }
+        .encode()
+    }
 
-        //add lenght to start (4 bytes)
-        let length = data.len() as u32;
-        let length_bytes = length.to_le_bytes();
-        data[..4].copy_from_slice(&length_bytes);
-        data
+    // player came into view, carries the full player so the client can spawn it
+    pub fn player_enter_view_to_bytes(&self, player_id: u32) -> Option<Vec<u8>> {
+        let player = self.players.get(&player_id)?.clone();
+        Some(Message::PlayerEnterView { player }.encode())
     }
 
-    pub fn chunk_to_bytes_rle(&self, x: i32, z: i32) -> Vec<u8> {
-        let mut data = Vec::new();
-        data.resize(4, 1); // Pre-allocate length header bytes (byte index 0-3)
+    // moves a position into the world's bounds, up to one world height above and below it
+    pub fn clamp_position(position: (f32, f32, f32)) -> (f32, f32, f32) {
+        let height = CHUNK_HEIGHT as f32;
+        (
+            position.0.clamp(-MAX_HORIZONTAL_COORDINATE, MAX_HORIZONTAL_COORDINATE),
+            position.1.clamp(-height, height * 2.0),
+            position.2.clamp(-MAX_HORIZONTAL_COORDINATE, MAX_HORIZONTAL_COORDINATE),
+        )
+    }
 
-        let data_identifier = DataIdentifier::ChunkData;
-        data.push(data_identifier as u8);
+    // chunk x,z that contains a world position
+    pub fn chunk_coords_of(position: (f32, f32, f32)) -> (i32, i32) {
+        let chunk_size = CHUNK_SIZE as f32;
+        (
+            (position.0 / chunk_size).floor() as i32,
+            (position.2 / chunk_size).floor() as i32,
+        )
+    }
 
-        let chunk = self.chunks.get(&(x, z)).unwrap().clone();
-        data.extend(chunk.coords.0.to_le_bytes());
-        data.extend(chunk.coords.1.to_le_bytes());
+    // ids of players whose chunk is within radius (in chunks) of position
+    pub fn players_in_range(&self, position: (f32, f32, f32), radius: i32) -> HashSet<u32> {
+        let (chunk_x, chunk_z) = World::chunk_coords_of(position);
+        self.players
+            .values()
+            .filter(|player| {
+                let (player_chunk_x, player_chunk_z) = World::chunk_coords_of(player.position);
+                // abs_diff, coordinates far apart overflow a subtraction
+                i64::from(player_chunk_x.abs_diff(chunk_x)) <= i64::from(radius)
+                    && i64::from(player_chunk_z.abs_diff(chunk_z)) <= i64::from(radius)
+            })
+            .map(|player| player.id)
+            .collect()
+    }
+
+    // run length encoded voxel ids of a chunk as (run length, id) pairs
+    pub fn chunk_rle(&self, x: i32, z: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+        let chunk = self.chunks.get(&(x, z)).unwrap();
 
-        let mut prev_voxel: Option<Voxel> = None;
+        let mut prev_voxel: Option<&Voxel> = None;
         let mut run_length: u8 = 0;
 
-        for voxel in chunk.voxels {
-            if let Some(prev_voxel) = &prev_voxel {
+        for voxel in &chunk.voxels {
+            if let Some(prev_voxel) = prev_voxel {
                 if voxel.id == prev_voxel.id && run_length < 255 {
                     run_length += 1;
                     continue;
@@ -149,10 +324,11 @@ impl World {
             prev_voxel = Some(voxel);
             run_length = 1;
         }
-
-        let length = data.len() as u32;
-        let length_bytes = length.to_le_bytes();
-        data[..4].copy_from_slice(&length_bytes);
+        // last run
+        if let Some(prev_voxel) = prev_voxel {
+            data.push(run_length);
+            data.push(prev_voxel.id);
+        }
 
         data
     }
@@ -162,47 +338,61 @@ impl World {
         world: Arc<RwLock<World>>,
         client_manager: Arc<RwLock<ClientManager>>,
     ) {
-        let mut generated_chunks: HashSet<(i32, i32)> = HashSet::new();
+        // starting chunks exist already
+        let mut generated_chunks: HashSet<(i32, i32)> =
+            world.read().await.chunks.keys().cloned().collect();
 
         loop {
-            {
-                // Calculate demand for chunks
-                let mut client_manager = client_manager.write().await;
-                client_manager.calculate_demanded_chunks().await;
-            }
-
-            // Check for connected clients
-            let client_count = {
+            // Calculate demand for chunks of this world
+            let demanded_chunks: Vec<(i32, i32, i32)> = {
                 let client_manager = client_manager.read().await;
-                client_manager.clients.len()
+                client_manager.calculate_demanded_chunks(&world).await
             };
 
-            if client_count == 0 {
+            if demanded_chunks.is_empty() {
                 tokio::time::sleep(std::time::Duration::from_millis(100)).await;
                 continue; // No clients, continue the loop
             }
 
-            // Get all demanded chunks
-            let demanded_chunks: Vec<(i32, i32, i32)> = {
-                let client_manager = client_manager.read().await;
-                client_manager.demanded_chunks.clone()
-            };
-
             // Generate the demanded chunks
             for chunk in demanded_chunks {
                 let x = chunk.0;
                 let z = chunk.1;
                 if !generated_chunks.contains(&(x, z)) {
                     let timer = Instant::now();
-                    let generated_chunk = Chunk::new(x, z);
+                    let (storage, generator) = {
+                        let world = world.read().await;
+                        (world.storage.clone(), world.generator.clone())
+                    };
+                    // region file reads and generation run off the async runtime
+                    let generated_chunk = match tokio::task::spawn_blocking(move || {
+                        World::load_or_generate(storage.as_deref(), x, z, &generator)
+                    })
+                    .await
+                    {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            eprintln!("Failed to generate chunk ({},{}): {}", x, z, e);
+                            continue;
+                        }
+                    };
                     {
                         let mut world = world.write().await;
                         world.chunks.insert((x, z), generated_chunk);
                         generated_chunks.insert((x, z));
                     }
+                    // queue the new chunk to every client waiting for it
+                    {
+                        let client_manager = client_manager.read().await;
+                        for client_arc in client_manager.clients.values() {
+                            let mut client = client_arc.write().await;
+                            if Arc::ptr_eq(&client.world, &world) && client.demands_chunk(x, z) {
+                                client.request_chunk(x, z);
+                            }
+                        }
+                    }
                     // Metrics (Assuming CHUNK_GENERATION_TIME and CHUNK_GENERATED_COUNTER are defined elsewhere)
                     CHUNK_GENERATION_TIME.observe(timer.elapsed().as_millis() as f64);
-                    CHUNK_GENERATED_COUNTER.inc();
                 }
             }
 
@@ -210,31 +400,138 @@ impl World {
             tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         }
     }
-    // updates world
+    // writes the chunks modified since the last save to the world's region files
+    pub async fn save_modified_chunks(world: &Arc<RwLock<World>>) {
+        let (storage, chunks) = {
+            let mut world = world.write().await;
+            let Some(storage) = world.storage.clone() else {
+                return;
+            };
+            let modified_chunks: Vec<(i32, i32)> = world.modified_chunks.drain().collect();
+            let chunks: Vec<((i32, i32), Vec<u8>)> = modified_chunks
+                .into_iter()
+                .filter_map(|coords| {
+                    let chunk = world.chunks.get(&coords)?;
+                    Some((coords, chunk.to_section_bytes()))
+                })
+                .collect();
+            (storage, chunks)
+        };
+        if chunks.is_empty() {
+            return;
+        }
+        // file writes run outside the world lock
+        let failed = tokio::task::spawn_blocking(move || {
+            let mut failed = Vec::new();
+            for ((x, z), data) in chunks {
+                if let Err(e) = storage.save_chunk(x, z, &data) {
+                    eprintln!("Failed to save chunk ({},{}): {}", x, z, e);
+                    failed.push((x, z));
+                }
+            }
+            failed
+        })
+        .await
+        .unwrap_or_default();
+        // retried with the next save
+        world.write().await.modified_chunks.extend(failed);
+    }
+
+    // saves modified chunks every save interval
+    pub async fn world_save_task(world: Arc<RwLock<World>>, save_interval: u64) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_millis(save_interval));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            World::save_modified_chunks(&world).await;
+        }
+    }
+
+    // updates every world once per tick, so all worlds share the server tick
     pub async fn world_update_task(
-        world: Arc<RwLock<World>>,
+        registry: Arc<WorldRegistry>,
         client_manager: Arc<RwLock<ClientManager>>,
         update_interval: u64, // World update intervals in milliseconds
     ) {
+        // fixed rate so ticks stay evenly spaced, a slow update delays the next one instead of bursting
+        let mut interval = tokio::time::interval(std::time::Duration::from_millis(update_interval));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         loop {
-            // update clients positions,rotation,state to world
-            // get a copy of clients
-            let client_manager_clone = client_manager.read().await;
-            let client_data = client_manager_clone.get_all_client_data().await;
-
-            //iterate trough clients and make player objects from them
-            let mut players: Vec<Player> = vec![];
-            for (id, position, rotation, state) in client_data {
-                let mut world = world.write().await;
-                world.add_player(Player {
-                    id,
-                    position,
-                    rotation,
-                    state,
-                });
+            interval.tick().await;
+            let tick = advance_tick();
+            let server_time = server_time_ms();
+            for world_arc in registry.worlds.values() {
+                World::update(world_arc, &client_manager, tick, server_time, update_interval).await;
+            }
+        }
+    }
+
+    // one tick of a world: moves its players and entities and sends them to the world's clients
+    async fn update(
+        world_arc: &Arc<RwLock<World>>,
+        client_manager: &Arc<RwLock<ClientManager>>,
+        tick: u64,
+        server_time: u64,
+        update_interval: u64,
+    ) {
+        // update clients positions,rotation,state to world
+        // get a copy of clients
+        let client_manager_clone = client_manager.read().await;
+        // clients in this world
+        let mut clients = Vec::new();
+        for client_arc in client_manager_clone.clients.values() {
+            if Arc::ptr_eq(&client_arc.read().await.world, world_arc) {
+                clients.push(client_arc.clone());
+            }
+        }
+        let mut world = world_arc.write().await;
+        // authoritative movement moves clients before their positions are copied to the world
+        if CONFIG.authoritative_movement {
+            simulate_clients(&world, clients.iter(), tick, update_interval as f32 / 1000.0).await;
+        }
+        let client_data = client_manager_clone.get_all_client_data(world_arc).await;
+        world.update_entities(tick, update_interval as f32 / 1000.0);
+
+        //iterate trough clients and make player objects from them
+        for (id, position, rotation, state) in client_data {
+            world.add_player(Player {
+                id,
+                position,
+                rotation,
+                state,
+            });
+        }
+
+        // queue players data to every client, only players within view radius are sent
+        for client_arc in &clients {
+            let mut client = client_arc.write().await;
+            // moved to another world since the clients were collected
+            if !Arc::ptr_eq(&client.world, world_arc) {
+                continue;
+            }
+            let visible_players =
+                world.players_in_range(client.position, CONFIG.player_view_radius);
+
+            for &player_id in visible_players.difference(&client.visible_players) {
+                if let Some(data) = world.player_enter_view_to_bytes(player_id) {
+                    client.outbound.push_data(Priority::Entity, data);
+                }
+            }
+            for &player_id in client.visible_players.difference(&visible_players) {
+                let data = Message::PlayerLeaveView { player_id }.encode();
+                client.outbound.push_data(Priority::Entity, data);
+            }
+
+            // over UDP when bound, otherwise replaces an unsent older snapshot
+            let player_data = world.players_to_bytes(&visible_players, tick, server_time);
+            if !client.send_unreliable(&player_data) {
+                client.outbound.push_latest(Priority::Entity, player_data);
             }
+            client.visible_players = visible_players;
 
-            tokio::time::sleep(std::time::Duration::from_millis(update_interval)).await;
+            replicate_entities(&world, &mut client, tick);
         }
+        drop(world);
+        drop(client_manager_clone);
     }
 }